@@ -0,0 +1,848 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use chrono::{Datelike, Local, TimeZone, Timelike};
+use flate2::read::{DeflateDecoder, GzDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::{Compression, Crc};
+use iron::response::WriteBody;
+
+use crate::filter::EntryFilter;
+use crate::util::system_time_to_date_time;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    pub const ALL: [ArchiveFormat; 2] = [ArchiveFormat::Zip, ArchiveFormat::TarGz];
+
+    /// Parse the value of the `?download=` query parameter
+    pub fn from_query(value: &str) -> Option<ArchiveFormat> {
+        match value {
+            "zip" => Some(ArchiveFormat::Zip),
+            "tar.gz" | "tgz" => Some(ArchiveFormat::TarGz),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// Response body streaming a directory as an archive, nothing is buffered or written to disk
+pub struct DirectoryArchive {
+    format: ArchiveFormat,
+    dir: PathBuf,
+    /// Top level folder name inside the archive
+    prefix: String,
+    /// Only archive these entries of `dir` (multi-select)
+    selected: Option<Vec<OsString>>,
+    filter: Arc<EntryFilter>,
+}
+
+impl DirectoryArchive {
+    pub fn new(
+        format: ArchiveFormat,
+        dir: PathBuf,
+        prefix: String,
+        selected: Option<Vec<OsString>>,
+        filter: Arc<EntryFilter>,
+    ) -> DirectoryArchive {
+        DirectoryArchive {
+            format,
+            dir,
+            prefix,
+            selected,
+            filter,
+        }
+    }
+
+    /// Visit every file and directory (depth first, directories before their content)
+    fn walk<F>(&self, mut visit: F) -> io::Result<()>
+    where
+        F: FnMut(&Path, &str, &fs::Metadata) -> io::Result<()>,
+    {
+        let mut visited = HashSet::new();
+        if let Ok(path) = self.dir.canonicalize() {
+            visited.insert(path);
+        }
+        let mut names = read_dir_names(&self.dir)?;
+        if let Some(ref selected) = self.selected {
+            names.retain(|name| selected.contains(name));
+        }
+        for name in names {
+            walk_entry(
+                &self.dir.join(&name),
+                &format!("{}/{}", self.prefix, name.to_string_lossy()),
+                &self.filter,
+                &mut visited,
+                &mut visit,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Names that are not valid UTF-8 are stored lossy in the archive
+fn read_dir_names(dir: &Path) -> io::Result<Vec<OsString>> {
+    let mut names = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name())
+        .collect::<Vec<OsString>>();
+    names.sort();
+    Ok(names)
+}
+
+fn walk_entry<F>(
+    path: &Path,
+    name: &str,
+    filter: &EntryFilter,
+    visited: &mut HashSet<PathBuf>,
+    visit: &mut F,
+) -> io::Result<()>
+where
+    F: FnMut(&Path, &str, &fs::Metadata) -> io::Result<()>,
+{
+    // Unreadable entries are skipped instead of aborting the whole download
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+    if filter.is_filtered_entry(path, metadata.is_dir()) {
+        return Ok(());
+    }
+    if metadata.is_dir() {
+        // Guard against symlink loops
+        if !path
+            .canonicalize()
+            .map(|real_path| visited.insert(real_path))
+            .unwrap_or(false)
+        {
+            return Ok(());
+        }
+        visit(path, name, &metadata)?;
+        for child in read_dir_names(path).unwrap_or_default() {
+            walk_entry(
+                &path.join(&child),
+                &format!("{}/{}", name, child.to_string_lossy()),
+                filter,
+                visited,
+                visit,
+            )?;
+        }
+        Ok(())
+    } else if metadata.is_file() {
+        visit(path, name, &metadata)
+    } else {
+        Ok(())
+    }
+}
+
+impl WriteBody for DirectoryArchive {
+    fn write_body(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        match self.format {
+            ArchiveFormat::Zip => {
+                let mut zip = ZipStream::new(w);
+                self.walk(|path, name, metadata| {
+                    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    if metadata.is_dir() {
+                        zip.add_dir(name, modified)
+                    } else {
+                        let file = fs::File::open(path)?;
+                        zip.add_file(name, modified, metadata.len(), file.take(metadata.len()))
+                    }
+                })?;
+                zip.finish()
+            }
+            ArchiveFormat::TarGz => {
+                let mut tar = tar::Builder::new(GzEncoder::new(w, Compression::default()));
+                self.walk(|path, name, metadata| {
+                    let mut header = tar::Header::new_gnu();
+                    header.set_metadata(metadata);
+                    if metadata.is_dir() {
+                        header.set_size(0);
+                        tar.append_data(&mut header, name, io::empty())
+                    } else {
+                        let file = fs::File::open(path)?;
+                        tar.append_data(&mut header, name, file.take(metadata.len()))
+                    }
+                })?;
+                tar.into_inner()?.finish().map(|_| ())
+            }
+        }
+    }
+}
+
+/// Writer keeping track of the current offset
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct ZipEntry {
+    name: String,
+    dos_time: u16,
+    dos_date: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    offset: u64,
+    is_dir: bool,
+}
+
+const ZIP64_LIMIT: u64 = 0xFFFF_FFFF;
+/// Version 4.5: zip64 extensions
+const ZIP_VERSION: u16 = 45;
+/// General purpose flags: data descriptor (bit 3) and UTF-8 file names (bit 11)
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+const FLAG_UTF8: u16 = 1 << 11;
+const METHOD_STORE: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+
+/// Minimal zip writer for non seekable streams
+///
+/// Sizes and CRC-32 are written in data descriptors after each file, zip64 records are used
+/// when a size, an offset or the number of entries do not fit in the classic format.
+struct ZipStream<W: Write> {
+    inner: CountingWriter<W>,
+    entries: Vec<ZipEntry>,
+}
+
+impl<W: Write> ZipStream<W> {
+    fn new(inner: W) -> ZipStream<W> {
+        ZipStream {
+            inner: CountingWriter { inner, count: 0 },
+            entries: Vec::new(),
+        }
+    }
+
+    fn add_dir(&mut self, name: &str, modified: SystemTime) -> io::Result<()> {
+        let (dos_time, dos_date) = dos_date_time(modified);
+        let entry = ZipEntry {
+            name: format!("{}/", name),
+            dos_time,
+            dos_date,
+            method: METHOD_STORE,
+            crc: 0,
+            compressed_size: 0,
+            size: 0,
+            offset: self.inner.count,
+            is_dir: true,
+        };
+        self.write_local_header(&entry, FLAG_UTF8, false)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn add_file<R: Read>(
+        &mut self,
+        name: &str,
+        modified: SystemTime,
+        size_hint: u64,
+        mut reader: R,
+    ) -> io::Result<()> {
+        let (dos_time, dos_date) = dos_date_time(modified);
+        let zip64 = size_hint >= ZIP64_LIMIT;
+        let mut entry = ZipEntry {
+            name: name.to_owned(),
+            dos_time,
+            dos_date,
+            method: METHOD_DEFLATE,
+            crc: 0,
+            compressed_size: 0,
+            size: 0,
+            offset: self.inner.count,
+            is_dir: false,
+        };
+        self.write_local_header(&entry, FLAG_DATA_DESCRIPTOR | FLAG_UTF8, zip64)?;
+
+        let start = self.inner.count;
+        let mut crc = Crc::new();
+        let mut size = 0u64;
+        let mut encoder = DeflateEncoder::new(&mut self.inner, Compression::default());
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            crc.update(&buf[..n]);
+            size += n as u64;
+            encoder.write_all(&buf[..n])?;
+        }
+        encoder.finish()?;
+        entry.crc = crc.sum();
+        entry.size = size;
+        entry.compressed_size = self.inner.count - start;
+        // The file grew past its size when it was added, its sizes do not fit
+        // the classic data descriptor
+        if !zip64 && (entry.size >= ZIP64_LIMIT || entry.compressed_size >= ZIP64_LIMIT) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} grew past 4 GiB while being archived", name),
+            ));
+        }
+
+        // Data descriptor
+        let w = &mut self.inner;
+        w.write_all(&0x0807_4b50u32.to_le_bytes())?;
+        w.write_all(&entry.crc.to_le_bytes())?;
+        if zip64 {
+            w.write_all(&entry.compressed_size.to_le_bytes())?;
+            w.write_all(&entry.size.to_le_bytes())?;
+        } else {
+            w.write_all(&(entry.compressed_size as u32).to_le_bytes())?;
+            w.write_all(&(entry.size as u32).to_le_bytes())?;
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn write_local_header(&mut self, entry: &ZipEntry, flags: u16, zip64: bool) -> io::Result<()> {
+        let w = &mut self.inner;
+        w.write_all(&0x0403_4b50u32.to_le_bytes())?;
+        w.write_all(&ZIP_VERSION.to_le_bytes())?;
+        w.write_all(&flags.to_le_bytes())?;
+        w.write_all(&entry.method.to_le_bytes())?;
+        w.write_all(&entry.dos_time.to_le_bytes())?;
+        w.write_all(&entry.dos_date.to_le_bytes())?;
+        // CRC-32 and sizes are in the data descriptor
+        w.write_all(&0u32.to_le_bytes())?;
+        let size_field = if zip64 { ZIP64_LIMIT as u32 } else { 0 };
+        w.write_all(&size_field.to_le_bytes())?;
+        w.write_all(&size_field.to_le_bytes())?;
+        w.write_all(&(entry.name.len() as u16).to_le_bytes())?;
+        w.write_all(&(if zip64 { 20u16 } else { 0u16 }).to_le_bytes())?;
+        w.write_all(entry.name.as_bytes())?;
+        if zip64 {
+            w.write_all(&0x0001u16.to_le_bytes())?;
+            w.write_all(&16u16.to_le_bytes())?;
+            w.write_all(&0u64.to_le_bytes())?;
+            w.write_all(&0u64.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let cd_offset = self.inner.count;
+        for entry in &self.entries {
+            let w = &mut self.inner;
+            // Zip64 extra field holds the values overflowing the classic fields, in this order
+            let mut extra = Vec::new();
+            if entry.size >= ZIP64_LIMIT {
+                extra.extend_from_slice(&entry.size.to_le_bytes());
+            }
+            if entry.compressed_size >= ZIP64_LIMIT {
+                extra.extend_from_slice(&entry.compressed_size.to_le_bytes());
+            }
+            if entry.offset >= ZIP64_LIMIT {
+                extra.extend_from_slice(&entry.offset.to_le_bytes());
+            }
+            let flags = if entry.is_dir {
+                FLAG_UTF8
+            } else {
+                FLAG_DATA_DESCRIPTOR | FLAG_UTF8
+            };
+            // Unix permissions in the high 16 bits, MS-DOS directory attribute in the low ones
+            let external_attributes: u32 = if entry.is_dir {
+                (0o040_755 << 16) | 0x10
+            } else {
+                0o100_644 << 16
+            };
+
+            w.write_all(&0x0201_4b50u32.to_le_bytes())?;
+            // Made by: Unix
+            w.write_all(&((3u16 << 8) | ZIP_VERSION).to_le_bytes())?;
+            w.write_all(&ZIP_VERSION.to_le_bytes())?;
+            w.write_all(&flags.to_le_bytes())?;
+            w.write_all(&entry.method.to_le_bytes())?;
+            w.write_all(&entry.dos_time.to_le_bytes())?;
+            w.write_all(&entry.dos_date.to_le_bytes())?;
+            w.write_all(&entry.crc.to_le_bytes())?;
+            w.write_all(&(entry.compressed_size.min(ZIP64_LIMIT) as u32).to_le_bytes())?;
+            w.write_all(&(entry.size.min(ZIP64_LIMIT) as u32).to_le_bytes())?;
+            w.write_all(&(entry.name.len() as u16).to_le_bytes())?;
+            let extra_len = if extra.is_empty() { 0 } else { extra.len() + 4 };
+            w.write_all(&(extra_len as u16).to_le_bytes())?;
+            // Comment length, disk number, internal attributes
+            w.write_all(&0u16.to_le_bytes())?;
+            w.write_all(&0u16.to_le_bytes())?;
+            w.write_all(&0u16.to_le_bytes())?;
+            w.write_all(&external_attributes.to_le_bytes())?;
+            w.write_all(&(entry.offset.min(ZIP64_LIMIT) as u32).to_le_bytes())?;
+            w.write_all(entry.name.as_bytes())?;
+            if !extra.is_empty() {
+                w.write_all(&0x0001u16.to_le_bytes())?;
+                w.write_all(&(extra.len() as u16).to_le_bytes())?;
+                w.write_all(&extra)?;
+            }
+        }
+        let cd_end = self.inner.count;
+        let cd_size = cd_end - cd_offset;
+        let count = self.entries.len() as u64;
+
+        let w = &mut self.inner;
+        if count >= 0xFFFF || cd_size >= ZIP64_LIMIT || cd_offset >= ZIP64_LIMIT {
+            // Zip64 end of central directory record
+            w.write_all(&0x0606_4b50u32.to_le_bytes())?;
+            w.write_all(&44u64.to_le_bytes())?;
+            w.write_all(&((3u16 << 8) | ZIP_VERSION).to_le_bytes())?;
+            w.write_all(&ZIP_VERSION.to_le_bytes())?;
+            w.write_all(&0u32.to_le_bytes())?;
+            w.write_all(&0u32.to_le_bytes())?;
+            w.write_all(&count.to_le_bytes())?;
+            w.write_all(&count.to_le_bytes())?;
+            w.write_all(&cd_size.to_le_bytes())?;
+            w.write_all(&cd_offset.to_le_bytes())?;
+            // Zip64 end of central directory locator
+            w.write_all(&0x0706_4b50u32.to_le_bytes())?;
+            w.write_all(&0u32.to_le_bytes())?;
+            w.write_all(&cd_end.to_le_bytes())?;
+            w.write_all(&1u32.to_le_bytes())?;
+        }
+        // End of central directory record
+        w.write_all(&0x0605_4b50u32.to_le_bytes())?;
+        w.write_all(&0u16.to_le_bytes())?;
+        w.write_all(&0u16.to_le_bytes())?;
+        w.write_all(&(count.min(0xFFFF) as u16).to_le_bytes())?;
+        w.write_all(&(count.min(0xFFFF) as u16).to_le_bytes())?;
+        w.write_all(&(cd_size.min(ZIP64_LIMIT) as u32).to_le_bytes())?;
+        w.write_all(&(cd_offset.min(ZIP64_LIMIT) as u32).to_le_bytes())?;
+        w.write_all(&0u16.to_le_bytes())?;
+        w.flush()
+    }
+}
+
+/// MS-DOS time and date (local time, 2 seconds resolution, from 1980)
+fn dos_date_time(t: SystemTime) -> (u16, u16) {
+    let dt = system_time_to_date_time(t);
+    if dt.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = (dt.hour() << 11) | (dt.minute() << 5) | (dt.second() / 2);
+    let date = (((dt.year() - 1980) as u32) << 9) | (dt.month() << 5) | dt.day();
+    (time as u16, date as u16)
+}
+
+/// Archive formats which can be browsed like directories
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    pub fn from_path(path: &Path) -> Option<ArchiveKind> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// A file or directory stored in an archive
+pub struct Member {
+    /// Path inside the archive, without leading and trailing `/`
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: SystemTime,
+    /// Offset of the data in the archive file, only when stored uncompressed
+    pub data_offset: Option<u64>,
+    /// Offset and compressed size of deflated zip data
+    deflated: Option<(u64, u64)>,
+    /// Position in the archive (zip index, or number of the tar entry)
+    index: usize,
+}
+
+/// Split a missing path into an archive file (under `root`) and the path inside of it
+///
+/// Example: `/srv/builds/site.zip/css/main.css` => (`/srv/builds/site.zip`, `css/main.css`)
+pub fn split_archive_path(root: &Path, fs_path: &Path) -> Option<(PathBuf, String)> {
+    for ancestor in fs_path.ancestors().skip(1) {
+        if !ancestor.starts_with(root) || ancestor == root {
+            break;
+        }
+        if ArchiveKind::from_path(ancestor).is_some() && ancestor.is_file() {
+            let inner = fs_path
+                .strip_prefix(ancestor)
+                .ok()?
+                .iter()
+                .map(|s| s.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            return Some((ancestor.to_owned(), inner));
+        }
+    }
+    None
+}
+
+/// Normalize a stored member path, `None` for paths escaping the archive
+fn normalize_name(raw_name: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in Path::new(raw_name).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?.to_owned()),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("/"))
+    }
+}
+
+fn zip_error(err: zip::result::ZipError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Archives whose members stay cached
+const MEMBER_CACHE_LIMIT: usize = 32;
+
+struct CachedMembers {
+    modified: SystemTime,
+    len: u64,
+    members: Arc<Vec<Member>>,
+}
+
+/// Tables of content of the archives browsed, read again once the archive file
+/// changes
+#[derive(Default)]
+pub struct MemberCache {
+    archives: Mutex<HashMap<PathBuf, CachedMembers>>,
+}
+
+impl MemberCache {
+    /// Members of the archive at `path`, read on first use
+    pub fn members(&self, kind: ArchiveKind, path: &Path) -> io::Result<Arc<Vec<Member>>> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?;
+        let len = metadata.len();
+        if let Some(cached) = self.archives.lock().unwrap().get(path) {
+            if cached.modified == modified && cached.len == len {
+                return Ok(Arc::clone(&cached.members));
+            }
+        }
+        let members = Arc::new(read_members(kind, path)?);
+        let mut archives = self.archives.lock().unwrap();
+        if archives.len() >= MEMBER_CACHE_LIMIT && !archives.contains_key(path) {
+            archives.clear();
+        }
+        archives.insert(
+            path.to_owned(),
+            CachedMembers {
+                modified,
+                len,
+                members: Arc::clone(&members),
+            },
+        );
+        Ok(members)
+    }
+}
+
+/// Read the table of content of an archive
+fn read_members(kind: ArchiveKind, path: &Path) -> io::Result<Vec<Member>> {
+    let mut members = Vec::new();
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(fs::File::open(path)?).map_err(zip_error)?;
+            for i in 0..zip.len() {
+                // Encrypted data is left to the zip reader, which refuses it
+                let readable = zip.by_index(i).is_ok();
+                let file = zip.by_index_raw(i).map_err(zip_error)?;
+                let name = match normalize_name(file.name()) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let direct = readable && !file.is_dir();
+                let stored = file.compression() == zip::CompressionMethod::Stored;
+                let deflated = file.compression() == zip::CompressionMethod::Deflated;
+                let modified = file.last_modified();
+                members.push(Member {
+                    name,
+                    is_dir: file.is_dir(),
+                    size: file.size(),
+                    modified: Local
+                        .with_ymd_and_hms(
+                            modified.year().into(),
+                            modified.month().into(),
+                            modified.day().into(),
+                            modified.hour().into(),
+                            modified.minute().into(),
+                            modified.second().into(),
+                        )
+                        .earliest()
+                        .map(SystemTime::from)
+                        .unwrap_or(SystemTime::UNIX_EPOCH),
+                    data_offset: if direct && stored {
+                        Some(file.data_start())
+                    } else {
+                        None
+                    },
+                    deflated: if direct && deflated {
+                        Some((file.data_start(), file.compressed_size()))
+                    } else {
+                        None
+                    },
+                    index: i,
+                });
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let file = fs::File::open(path)?;
+            let reader: Box<dyn Read> = if kind == ArchiveKind::TarGz {
+                Box::new(GzDecoder::new(file))
+            } else {
+                Box::new(file)
+            };
+            let mut tar = tar::Archive::new(reader);
+            for (index, entry) in tar.entries()?.enumerate() {
+                let entry = entry?;
+                let entry_type = entry.header().entry_type();
+                if !entry_type.is_file() && !entry_type.is_dir() {
+                    continue;
+                }
+                let name = match normalize_name(&entry.path()?.to_string_lossy()) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                members.push(Member {
+                    name,
+                    is_dir: entry_type.is_dir(),
+                    size: entry.size(),
+                    modified: SystemTime::UNIX_EPOCH
+                        + Duration::from_secs(entry.header().mtime().unwrap_or(0)),
+                    data_offset: if kind == ArchiveKind::Tar && entry_type.is_file() {
+                        Some(entry.raw_file_position())
+                    } else {
+                        None
+                    },
+                    deflated: None,
+                    index,
+                });
+            }
+        }
+    }
+    Ok(members)
+}
+
+/// Whether `dir` is a (possibly implicit) directory of the archive
+pub fn is_member_dir(members: &[Member], dir: &str) -> bool {
+    let prefix = format!("{}/", dir);
+    members
+        .iter()
+        .any(|member| (member.is_dir && member.name == dir) || member.name.starts_with(&prefix))
+}
+
+/// Direct children of `dir` (empty for the archive root), `name` is the child name
+pub fn list_members(members: &[Member], dir: &str) -> Vec<Member> {
+    let prefix = if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", dir)
+    };
+    let mut children: BTreeMap<String, Member> = BTreeMap::new();
+    for member in members {
+        let rest = match member.name.strip_prefix(&prefix) {
+            Some(rest) if !rest.is_empty() => rest,
+            _ => continue,
+        };
+        match rest.find('/') {
+            // Directories are not always stored explicitly
+            Some(idx) => {
+                let name = &rest[..idx];
+                children.entry(name.to_owned()).or_insert_with(|| Member {
+                    name: name.to_owned(),
+                    is_dir: true,
+                    size: 0,
+                    modified: member.modified,
+                    data_offset: None,
+                    deflated: None,
+                    index: 0,
+                });
+            }
+            None => {
+                children.insert(
+                    rest.to_owned(),
+                    Member {
+                        name: rest.to_owned(),
+                        is_dir: member.is_dir,
+                        size: member.size,
+                        modified: member.modified,
+                        data_offset: member.data_offset,
+                        deflated: member.deflated,
+                        index: member.index,
+                    },
+                );
+            }
+        }
+    }
+    children.into_values().collect()
+}
+
+/// Response body extracting one archive member on the fly
+///
+/// Data stored uncompressed or deflated is read directly from the archive file,
+/// otherwise the archive is read once up to the member.
+pub struct MemberBody {
+    kind: ArchiveKind,
+    path: PathBuf,
+    size: u64,
+    data_offset: Option<u64>,
+    deflated: Option<(u64, u64)>,
+    index: usize,
+}
+
+impl MemberBody {
+    pub fn new(kind: ArchiveKind, path: PathBuf, member: &Member) -> MemberBody {
+        MemberBody {
+            kind,
+            path,
+            size: member.size,
+            data_offset: member.data_offset,
+            deflated: member.deflated,
+            index: member.index,
+        }
+    }
+}
+
+impl WriteBody for MemberBody {
+    fn write_body(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        if let Some(offset) = self.data_offset {
+            let mut file = fs::File::open(&self.path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            return io::copy(&mut file.take(self.size), w).map(|_| ());
+        }
+        if let Some((offset, compressed_size)) = self.deflated {
+            let mut file = fs::File::open(&self.path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            let mut data = DeflateDecoder::new(file.take(compressed_size)).take(self.size);
+            return io::copy(&mut data, w).map(|_| ());
+        }
+        let not_found = || {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("archive entry not found: {}", self.index),
+            )
+        };
+        match self.kind {
+            ArchiveKind::Zip => {
+                let mut zip =
+                    zip::ZipArchive::new(fs::File::open(&self.path)?).map_err(zip_error)?;
+                let mut file = zip.by_index(self.index).map_err(zip_error)?;
+                io::copy(&mut file, w).map(|_| ())
+            }
+            ArchiveKind::Tar | ArchiveKind::TarGz => {
+                let file = fs::File::open(&self.path)?;
+                let reader: Box<dyn Read> = if self.kind == ArchiveKind::TarGz {
+                    Box::new(GzDecoder::new(file))
+                } else {
+                    Box::new(file)
+                };
+                let mut tar = tar::Archive::new(reader);
+                let mut entry = tar.entries()?.nth(self.index).ok_or_else(not_found)??;
+                io::copy(&mut entry, w).map(|_| ())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_back(data: Vec<u8>) -> Vec<(String, Vec<u8>)> {
+        let mut zip = zip::ZipArchive::new(io::Cursor::new(data)).unwrap();
+        (0..zip.len())
+            .map(|i| {
+                let mut file = zip.by_index(i).unwrap();
+                let mut content = Vec::new();
+                file.read_to_end(&mut content).unwrap();
+                (file.name().to_owned(), content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn zip_stream_round_trip() {
+        let modified = SystemTime::now();
+        let mut data = Vec::new();
+        let mut zip = ZipStream::new(&mut data);
+        zip.add_dir("docs", modified).unwrap();
+        zip.add_file("docs/a.txt", modified, 5, &b"hello"[..])
+            .unwrap();
+        zip.add_file("empty", modified, 0, io::empty()).unwrap();
+        zip.add_file("ünïcode.txt", modified, 3, &b"abc"[..])
+            .unwrap();
+        zip.finish().unwrap();
+        assert_eq!(
+            read_back(data),
+            vec![
+                ("docs/".to_owned(), Vec::new()),
+                ("docs/a.txt".to_owned(), b"hello".to_vec()),
+                ("empty".to_owned(), Vec::new()),
+                ("ünïcode.txt".to_owned(), b"abc".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn zip_stream_zip64_entry_round_trip() {
+        // Announced as larger than 4 GiB: zip64 local header and data descriptor
+        let mut data = Vec::new();
+        let mut zip = ZipStream::new(&mut data);
+        zip.add_file("big", SystemTime::now(), ZIP64_LIMIT, &b"not so big"[..])
+            .unwrap();
+        zip.add_file("small", SystemTime::now(), 2, &b"ok"[..])
+            .unwrap();
+        zip.finish().unwrap();
+        assert_eq!(
+            read_back(data),
+            vec![
+                ("big".to_owned(), b"not so big".to_vec()),
+                ("small".to_owned(), b"ok".to_vec()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,58 @@
+(function () {
+  "use strict";
+  // Append the events of the followed file, keeping the page to a bounded size
+  var log = document.getElementById("log");
+  var status = document.getElementById("follow-status");
+  var autoscroll = document.getElementById("autoscroll");
+  if (!log || !window.EventSource) {
+    if (status) {
+      status.textContent = "Live updates are not supported by this browser";
+    }
+    return;
+  }
+  var MAX_LENGTH = 2 * 1024 * 1024;
+  var length = 0;
+
+  function scroll() {
+    if (autoscroll.checked) {
+      window.scrollTo(0, document.body.scrollHeight);
+    }
+  }
+
+  function trim() {
+    while (length > MAX_LENGTH && log.firstChild) {
+      length -= log.firstChild.textContent.length;
+      log.removeChild(log.firstChild);
+    }
+  }
+
+  function append(text) {
+    log.appendChild(document.createTextNode(text));
+    length += text.length;
+    trim();
+    scroll();
+  }
+
+  var source = new EventSource(log.getAttribute("data-src"));
+  source.onopen = function () {
+    status.textContent = "Following";
+  };
+  source.onmessage = function (event) {
+    append(event.data);
+  };
+  source.addEventListener("reset", function (event) {
+    var notice = document.createElement("span");
+    notice.className = "reset";
+    notice.textContent = event.data === "rotated" ? "File replaced" : "File truncated";
+    // Only the new content is kept
+    log.textContent = "";
+    log.appendChild(notice);
+    length = 0;
+    scroll();
+  });
+  source.onerror = function () {
+    status.textContent = source.readyState === EventSource.CLOSED
+      ? "Disconnected, reload the page to follow again"
+      : "Reconnecting…";
+  };
+})();
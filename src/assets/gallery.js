@@ -0,0 +1,71 @@
+(function () {
+  "use strict";
+  // Lightbox of the gallery: arrows (or h/l) switch images, Escape closes
+  var images = Array.prototype.slice.call(document.querySelectorAll(".gallery a.image"));
+  if (images.length === 0 || typeof HTMLDialogElement === "undefined") {
+    return;
+  }
+  var dialog = document.createElement("dialog");
+  dialog.className = "lightbox";
+  dialog.innerHTML =
+    '<figure><img alt=""/><figcaption></figcaption></figure>' +
+    '<button type="button" class="close" aria-label="Close">&times;</button>';
+  document.body.appendChild(dialog);
+  var image = dialog.querySelector("img");
+  var caption = dialog.querySelector("figcaption");
+  var current = 0;
+
+  // Images left by the filter
+  function shown() {
+    return images.filter(function (link) {
+      return !link.closest(".entry").hidden;
+    });
+  }
+
+  function show(index) {
+    var links = shown();
+    if (links.length === 0) {
+      return;
+    }
+    current = (index + links.length) % links.length;
+    image.src = links[current].href;
+    caption.textContent = links[current].closest(".entry").getAttribute("data-name");
+    if (!dialog.open) {
+      dialog.showModal();
+    }
+  }
+
+  images.forEach(function (link) {
+    link.addEventListener("click", function (event) {
+      if (event.button !== 0 || event.ctrlKey || event.metaKey || event.shiftKey) {
+        return;
+      }
+      event.preventDefault();
+      show(shown().indexOf(link));
+    });
+  });
+
+  dialog.addEventListener("keydown", function (event) {
+    // The listing shortcuts stay out of the lightbox
+    event.stopPropagation();
+    if (event.key === "ArrowRight" || event.key === "l" || event.key === " ") {
+      event.preventDefault();
+      show(current + 1);
+    } else if (event.key === "ArrowLeft" || event.key === "h") {
+      event.preventDefault();
+      show(current - 1);
+    }
+  });
+  dialog.addEventListener("click", function (event) {
+    if (event.target === dialog || event.target.classList.contains("close")) {
+      dialog.close();
+    }
+  });
+  dialog.addEventListener("close", function () {
+    var links = shown();
+    image.removeAttribute("src");
+    if (links[current]) {
+      links[current].focus();
+    }
+  });
+})();
@@ -0,0 +1,111 @@
+(function () {
+  "use strict";
+  var filter = document.getElementById("filter");
+  var rows = Array.prototype.slice.call(document.querySelectorAll(".entry"));
+  var empty = document.getElementById("filter-empty");
+  var play = document.querySelector("a.play[hidden]");
+
+  // Unsorted listings are sent before knowing whether they hold any media
+  if (play && document.querySelector('.entry a.preview-link[href$="?play"]')) {
+    play.hidden = false;
+  }
+
+  // Client-side filter of the current page, only shown when scripts run
+  if (filter) {
+    filter.hidden = false;
+    filter.addEventListener("input", function () {
+      var text = filter.value.toLowerCase();
+      var shown = 0;
+      rows.forEach(function (row) {
+        var match = row.getAttribute("data-name").toLowerCase().indexOf(text) !== -1;
+        row.hidden = !match;
+        if (match) {
+          shown += 1;
+        }
+      });
+      if (empty) {
+        empty.hidden = shown !== 0 || rows.length === 0;
+      }
+    });
+  }
+
+  function links() {
+    return Array.prototype.slice
+      .call(document.querySelectorAll(".up a.entry-link, .entry a.entry-link"))
+      .filter(function (link) {
+        return !link.closest(".up, .entry").hidden;
+      });
+  }
+
+  function move(step) {
+    var all = links();
+    if (all.length === 0) {
+      return;
+    }
+    var index = all.indexOf(document.activeElement);
+    if (index === -1) {
+      index = step > 0 ? 0 : all.length - 1;
+    } else {
+      index = Math.min(Math.max(index + step, 0), all.length - 1);
+    }
+    all[index].focus();
+  }
+
+  function follow(selector) {
+    var link = document.querySelector(selector);
+    if (link) {
+      window.location.href = link.href;
+    }
+  }
+
+  // j/k or arrows move between entries, Enter opens, "/" filters, Backspace goes up,
+  // n/p switch pages
+  document.addEventListener("keydown", function (event) {
+    if (event.ctrlKey || event.metaKey || event.altKey) {
+      return;
+    }
+    var typing = event.target.tagName === "INPUT";
+    if (typing) {
+      if (event.key === "Escape") {
+        if (event.target === filter && filter.value !== "") {
+          filter.value = "";
+          filter.dispatchEvent(new Event("input"));
+        }
+        event.target.blur();
+      } else if (event.key === "ArrowDown" && event.target === filter) {
+        event.preventDefault();
+        move(1);
+      }
+      return;
+    }
+    switch (event.key) {
+      case "ArrowDown":
+      case "j":
+        event.preventDefault();
+        move(1);
+        break;
+      case "ArrowUp":
+      case "k":
+        event.preventDefault();
+        move(-1);
+        break;
+      case "/":
+        if (filter) {
+          event.preventDefault();
+          filter.focus();
+        }
+        break;
+      case "Backspace":
+      case "h":
+        event.preventDefault();
+        follow(".up a.entry-link");
+        break;
+      case "n":
+        follow("a[rel=next]");
+        break;
+      case "p":
+        follow("a[rel=prev]");
+        break;
+    }
+  });
+})();
@@ -0,0 +1,84 @@
+(function () {
+  "use strict";
+  // Switch tracks in place and play the next one when a track ends
+  var player = document.getElementById("player");
+  var data = document.getElementById("tracks");
+  if (!player || !data) {
+    return;
+  }
+  var tracks = JSON.parse(data.textContent);
+  var current = parseInt(player.getAttribute("data-index"), 10) || 0;
+  var items = document.querySelectorAll(".playlist li");
+  var name = document.getElementById("track-name");
+  var prev = document.getElementById("prev");
+  var next = document.getElementById("next");
+
+  function neighbour(link, index) {
+    var track = tracks[index];
+    link.hidden = !track;
+    if (track) {
+      link.href = track.href + "?play";
+    }
+  }
+
+  function load(index, play) {
+    var track = tracks[index];
+    if (!track) {
+      return;
+    }
+    current = index;
+    while (player.firstChild) {
+      player.removeChild(player.firstChild);
+    }
+    player.className = track.video ? "video" : "audio";
+    player.src = track.href;
+    track.subtitles.forEach(function (subtitle, i) {
+      var element = document.createElement("track");
+      element.kind = "subtitles";
+      element.label = subtitle.label;
+      element.src = subtitle.href;
+      if (subtitle.lang) {
+        element.srclang = subtitle.lang;
+      }
+      element.default = i === 0;
+      player.appendChild(element);
+    });
+    Array.prototype.forEach.call(items, function (item, i) {
+      item.classList.toggle("current", i === index);
+      if (i === index) {
+        item.setAttribute("aria-current", "true");
+      } else {
+        item.removeAttribute("aria-current");
+      }
+    });
+    name.textContent = track.name;
+    document.title = track.name;
+    neighbour(prev, index - 1);
+    neighbour(next, index + 1);
+    history.replaceState(null, "", track.href + "?play");
+    if (play) {
+      var playing = player.play();
+      if (playing) {
+        playing.catch(function () {});
+      }
+    }
+  }
+
+  Array.prototype.forEach.call(items, function (item, i) {
+    item.querySelector("a").addEventListener("click", function (event) {
+      event.preventDefault();
+      load(i, true);
+    });
+  });
+  prev.addEventListener("click", function (event) {
+    event.preventDefault();
+    load(current - 1, true);
+  });
+  next.addEventListener("click", function (event) {
+    event.preventDefault();
+    load(current + 1, true);
+  });
+  player.addEventListener("ended", function () {
+    load(current + 1, true);
+  });
+})();
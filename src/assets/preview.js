@@ -0,0 +1,48 @@
+(function () {
+  "use strict";
+  // Click a column header of a CSV/TSV table to sort by it, again to reverse
+  var table = document.querySelector("table.csv");
+  if (!table) {
+    return;
+  }
+  var body = table.tBodies[0];
+  var sorted = { column: -1, reverse: false };
+
+  function compare(a, b) {
+    var x = parseFloat(a);
+    var y = parseFloat(b);
+    if (!isNaN(x) && !isNaN(y) && String(x) === a.trim() && String(y) === b.trim()) {
+      return x - y;
+    }
+    return a.localeCompare(b, undefined, { numeric: true, sensitivity: "base" });
+  }
+
+  Array.prototype.forEach.call(table.tHead.rows[0].cells, function (th, column) {
+    th.tabIndex = 0;
+    th.setAttribute("role", "button");
+    function sort() {
+      var reverse = sorted.column === column && !sorted.reverse;
+      var rows = Array.prototype.slice.call(body.rows);
+      rows.sort(function (a, b) {
+        var x = a.cells[column] ? a.cells[column].textContent : "";
+        var y = b.cells[column] ? b.cells[column].textContent : "";
+        return reverse ? compare(y, x) : compare(x, y);
+      });
+      rows.forEach(function (row) {
+        body.appendChild(row);
+      });
+      Array.prototype.forEach.call(table.tHead.rows[0].cells, function (cell) {
+        cell.removeAttribute("aria-sort");
+      });
+      th.setAttribute("aria-sort", reverse ? "descending" : "ascending");
+      sorted = { column: column, reverse: reverse };
+    }
+    th.addEventListener("click", sort);
+    th.addEventListener("keydown", function (event) {
+      if (event.key === "Enter" || event.key === " ") {
+        event.preventDefault();
+        sort();
+      }
+    });
+  });
+})();
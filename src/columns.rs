@@ -0,0 +1,405 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Serialize;
+
+use crate::filter::EntryFilter;
+
+/// Cached recursive sizes are recomputed after this long, even when the directory
+/// itself did not change (a nested file may have grown), and shown meanwhile
+const DIR_SIZE_TTL: Duration = Duration::from_secs(10);
+/// Directories too large to walk are tried again after this long
+const DIR_SIZE_RETRY: Duration = Duration::from_secs(300);
+/// A walk giving up past this depth or time leaves the size unknown
+const DIR_SIZE_MAX_DEPTH: usize = 32;
+const DIR_SIZE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Directories waiting for their size, more are queued again by later listings
+const DIR_SIZE_QUEUE: usize = 1000;
+/// Forget all cached sizes past this many directories
+const DIR_SIZE_CACHE_LIMIT: usize = 100_000;
+
+/// Optional listing column (`--columns`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    /// Unix mode string: `drwxr-xr-x`
+    Mode,
+    /// Owner and group names
+    Owner,
+    Mime,
+    /// Number of entries of a directory
+    Items,
+    /// Recursive size of a directory
+    DirSize,
+}
+
+impl Column {
+    pub const VALUES: [&'static str; 5] = ["mode", "owner", "mime", "items", "dirsize"];
+
+    pub fn from_arg(value: &str) -> Option<Column> {
+        match value {
+            "mode" => Some(Column::Mode),
+            "owner" => Some(Column::Owner),
+            "mime" => Some(Column::Mime),
+            "items" => Some(Column::Items),
+            "dirsize" => Some(Column::DirSize),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Mode => "mode",
+            Column::Owner => "owner",
+            Column::Mime => "mime",
+            Column::Items => "items",
+            Column::DirSize => "dirsize",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Column::Mode => "Mode",
+            Column::Owner => "Owner",
+            Column::Mime => "Type",
+            Column::Items => "Items",
+            Column::DirSize => "Total size",
+        }
+    }
+}
+
+/// Values of the optional columns of one entry, `None` when the column is not
+/// enabled or does not apply (archive members, files for `items`)
+///
+/// The MIME type is always part of the entry, it is not repeated here.
+#[derive(Default, Serialize)]
+pub struct ColumnValues {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir_size: Option<u64>,
+}
+
+struct CachedSize {
+    /// Modification time of the directory when computed
+    modified: SystemTime,
+    computed: Instant,
+    /// `None` when the walk gave up
+    size: Option<u64>,
+}
+
+#[derive(Default)]
+struct DirSizes {
+    cache: Mutex<HashMap<PathBuf, CachedSize>>,
+    /// Directories queued or being walked
+    pending: Mutex<HashSet<PathBuf>>,
+}
+
+/// The enabled optional columns, and what they need to be computed
+pub struct Columns {
+    columns: Vec<Column>,
+    dir_sizes: Arc<DirSizes>,
+    /// Queue of the thread walking directories for their size (`dirsize`)
+    dir_size_queue: Option<SyncSender<PathBuf>>,
+    names: Mutex<HashMap<(bool, u32), Option<String>>>,
+}
+
+impl Columns {
+    pub fn new(columns: Vec<Column>, filter: Arc<EntryFilter>) -> Columns {
+        let dir_sizes = Arc::new(DirSizes::default());
+        let dir_size_queue = if columns.contains(&Column::DirSize) {
+            let (tx, rx) = sync_channel::<PathBuf>(DIR_SIZE_QUEUE);
+            let dir_sizes = Arc::clone(&dir_sizes);
+            thread::spawn(move || {
+                for dir in rx {
+                    let modified = fs::metadata(&dir).and_then(|metadata| metadata.modified());
+                    if let Ok(modified) = modified {
+                        let size = walk_size(&dir, &filter);
+                        let mut cache = dir_sizes.cache.lock().unwrap();
+                        if cache.len() >= DIR_SIZE_CACHE_LIMIT {
+                            cache.clear();
+                        }
+                        cache.insert(
+                            dir.clone(),
+                            CachedSize {
+                                modified,
+                                computed: Instant::now(),
+                                size,
+                            },
+                        );
+                    }
+                    dir_sizes.pending.lock().unwrap().remove(&dir);
+                }
+            });
+            Some(tx)
+        } else {
+            None
+        };
+        Columns {
+            columns,
+            dir_sizes,
+            dir_size_queue,
+            names: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn has(&self, column: Column) -> bool {
+        self.columns.contains(&column)
+    }
+
+    /// Compute the enabled columns of the entry at `path`, whose symbolic link (if
+    /// any) is already resolved in `is_dir`
+    pub fn values(&self, path: &Path, is_dir: bool, filter: &EntryFilter) -> ColumnValues {
+        let mut values = ColumnValues::default();
+        if self.has(Column::Mode) {
+            // Of the link itself, shown as such
+            values.mode = fs::symlink_metadata(path)
+                .ok()
+                .map(|metadata| mode_string(&metadata));
+        }
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return values,
+        };
+        if self.has(Column::Owner) {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                values.owner = Some(self.user_name(metadata.uid()));
+                values.group = Some(self.group_name(metadata.gid()));
+            }
+        }
+        if is_dir {
+            if self.has(Column::Items) {
+                values.items = fs::read_dir(path).ok().map(|read_dir| {
+                    read_dir
+                        .flatten()
+                        .filter(|entry| {
+                            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                            !filter.is_filtered_entry(&entry.path(), is_dir)
+                        })
+                        .count() as u64
+                });
+            }
+            if self.has(Column::DirSize) {
+                values.dir_size = self.dir_size(path);
+            }
+        }
+        values
+    }
+
+    /// Cached total size of the files under `dir`, `None` until it is computed
+    ///
+    /// Sizes are computed in the background, and again when the modification
+    /// time of `dir` changes or after `DIR_SIZE_TTL`. Sizes only older than that
+    /// are still shown while they are recomputed.
+    pub fn dir_size(&self, dir: &Path) -> Option<u64> {
+        let modified = fs::metadata(dir)
+            .and_then(|metadata| metadata.modified())
+            .ok()?;
+        let (size, fresh) = match self.dir_sizes.cache.lock().unwrap().get(dir) {
+            Some(cached) => {
+                let ttl = if cached.size.is_some() {
+                    DIR_SIZE_TTL
+                } else {
+                    DIR_SIZE_RETRY
+                };
+                (
+                    cached.size.filter(|_| cached.modified == modified),
+                    cached.modified == modified && cached.computed.elapsed() < ttl,
+                )
+            }
+            None => (None, false),
+        };
+        if !fresh {
+            self.queue_dir_size(dir);
+        }
+        size
+    }
+
+    fn queue_dir_size(&self, dir: &Path) {
+        let queue = match self.dir_size_queue {
+            Some(ref queue) => queue,
+            None => return,
+        };
+        let mut pending = self.dir_sizes.pending.lock().unwrap();
+        if pending.contains(dir) {
+            return;
+        }
+        // A full queue is left to the next listing
+        if queue.try_send(dir.to_path_buf()).is_ok() {
+            pending.insert(dir.to_path_buf());
+        }
+    }
+
+    #[cfg(unix)]
+    fn user_name(&self, uid: u32) -> String {
+        self.cached_name((true, uid), lookup_user)
+    }
+
+    #[cfg(unix)]
+    fn group_name(&self, gid: u32) -> String {
+        self.cached_name((false, gid), lookup_group)
+    }
+
+    /// Name of a user (`true`) or group id, looked up outside of the lock (the
+    /// lookup can be slow, with a remote directory) then cached
+    #[cfg(unix)]
+    fn cached_name(&self, key: (bool, u32), lookup: fn(u32) -> Option<String>) -> String {
+        let cached = self.names.lock().unwrap().get(&key).cloned();
+        let name = match cached {
+            Some(name) => name,
+            None => {
+                let name = lookup(key.1);
+                self.names.lock().unwrap().insert(key, name.clone());
+                name
+            }
+        };
+        name.unwrap_or_else(|| key.1.to_string())
+    }
+}
+
+/// Total size of the files under `dir`, symbolic links and filtered entries are
+/// not counted, `None` past `DIR_SIZE_MAX_DEPTH` or `DIR_SIZE_TIMEOUT`
+fn walk_size(dir: &Path, filter: &EntryFilter) -> Option<u64> {
+    let deadline = Instant::now() + DIR_SIZE_TIMEOUT;
+    let mut size = 0;
+    let mut dirs = vec![(dir.to_path_buf(), 1)];
+    while let Some((dir, depth)) = dirs.pop() {
+        if Instant::now() >= deadline {
+            return None;
+        }
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if filter.is_filtered_entry(&entry.path(), metadata.is_dir()) {
+                continue;
+            }
+            if metadata.is_dir() {
+                if depth >= DIR_SIZE_MAX_DEPTH {
+                    return None;
+                }
+                dirs.push((entry.path(), depth + 1));
+            } else if metadata.is_file() {
+                size += metadata.len();
+            }
+        }
+    }
+    Some(size)
+}
+
+#[cfg(unix)]
+fn mode_string(metadata: &fs::Metadata) -> String {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_dir() {
+        'd'
+    } else if file_type.is_symlink() {
+        'l'
+    } else if file_type.is_block_device() {
+        'b'
+    } else if file_type.is_char_device() {
+        'c'
+    } else if file_type.is_fifo() {
+        'p'
+    } else if file_type.is_socket() {
+        's'
+    } else {
+        '-'
+    };
+    let mode = metadata.permissions().mode();
+    let mut s = String::with_capacity(10);
+    s.push(kind);
+    for (i, c) in "rwxrwxrwx".chars().enumerate() {
+        let bit = 0o400 >> i;
+        let special = match i {
+            2 if mode & 0o4000 != 0 => Some('s'),
+            5 if mode & 0o2000 != 0 => Some('s'),
+            8 if mode & 0o1000 != 0 => Some('t'),
+            _ => None,
+        };
+        s.push(match (special, mode & bit != 0) {
+            (Some(special), true) => special,
+            (Some(special), false) => special.to_ascii_uppercase(),
+            (None, true) => c,
+            (None, false) => '-',
+        });
+    }
+    s
+}
+
+#[cfg(not(unix))]
+fn mode_string(metadata: &fs::Metadata) -> String {
+    let kind = if metadata.is_dir() { 'd' } else { '-' };
+    let write = if metadata.permissions().readonly() {
+        '-'
+    } else {
+        'w'
+    };
+    format!("{}r{}-", kind, write)
+}
+
+/// Size of the buffer given to `getpwuid_r` and `getgrgid_r`
+#[cfg(unix)]
+const NAME_BUFFER_SIZE: usize = 16 * 1024;
+
+#[cfg(unix)]
+fn lookup_user(uid: u32) -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; NAME_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    // The name points into `buffer`, copied before it is dropped
+    let ret = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 || result.is_null() || passwd.pw_name.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(unix)]
+fn lookup_group(gid: u32) -> Option<String> {
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; NAME_BUFFER_SIZE];
+    let mut result = std::ptr::null_mut();
+    // The name points into `buffer`, copied before it is dropped
+    let ret = unsafe {
+        libc::getgrgid_r(
+            gid,
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 || result.is_null() || group.gr_name.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(group.gr_name) };
+    Some(name.to_string_lossy().into_owned())
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use glob::Pattern;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+/// Ignore files honoured with `--ignore-files`, the later ones take precedence
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// How symbolic links inside the root are treated (`--symlinks`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymlinkPolicy {
+    /// Follow every link, even out of the root
+    Follow,
+    /// Follow links whose resolved target is inside the root
+    WithinRoot,
+    /// Never follow links
+    Deny,
+}
+
+impl SymlinkPolicy {
+    pub const VALUES: [&'static str; 3] = ["follow", "within-root", "deny"];
+
+    pub fn from_arg(value: &str) -> Option<SymlinkPolicy> {
+        match value {
+            "follow" => Some(SymlinkPolicy::Follow),
+            "within-root" => Some(SymlinkPolicy::WithinRoot),
+            "deny" => Some(SymlinkPolicy::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// Ignore rules of one directory, reloaded when an ignore file changes
+struct IgnoreRules {
+    modified: Vec<Option<SystemTime>>,
+    matcher: Option<Arc<Gitignore>>,
+}
+
+/// Decides which files are hidden from listings, downloads and searches
+///
+/// Filtered paths are answered like missing files (404). Symbolic links are
+/// filtered according to the policy, so they can not lead out of the root.
+pub struct EntryFilter {
+    /// Canonical root directory
+    root: PathBuf,
+    symlinks: SymlinkPolicy,
+    /// Show dotfiles (`--hidden`)
+    hidden: bool,
+    /// `--exclude` patterns, matched against the name and the root relative path
+    exclude: Vec<Pattern>,
+    /// Honour `.gitignore` and `.ignore` files (`--ignore-files`)
+    ignore_files: bool,
+    /// Files of the server itself never served, such as the search index database
+    denied: RwLock<Vec<PathBuf>>,
+    rules: Mutex<HashMap<PathBuf, IgnoreRules>>,
+}
+
+impl EntryFilter {
+    pub fn new(
+        root: PathBuf,
+        symlinks: SymlinkPolicy,
+        hidden: bool,
+        exclude: Vec<Pattern>,
+        ignore_files: bool,
+    ) -> EntryFilter {
+        EntryFilter {
+            root,
+            symlinks,
+            hidden,
+            exclude,
+            ignore_files,
+            denied: RwLock::new(Vec::new()),
+            rules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Filter the file at `path` (canonical) from now on
+    pub fn deny(&self, path: PathBuf) {
+        self.denied.write().unwrap().push(path);
+    }
+
+    fn is_denied(&self, path: &Path) -> bool {
+        self.denied
+            .read()
+            .unwrap()
+            .iter()
+            .any(|denied| denied == path)
+    }
+
+    /// Whether `path` (absolute) or any of its parent directories below the root is filtered
+    pub fn is_filtered(&self, path: &Path, is_dir: bool) -> bool {
+        self.check(path, is_dir, false)
+    }
+
+    /// Whether a file named `name` would change the ignore rules (`--ignore-files`)
+    pub fn is_ignore_file(&self, name: &str) -> bool {
+        self.ignore_files && IGNORE_FILES.contains(&name)
+    }
+
+    /// Whether an entry of an already visible directory is filtered, only the last
+    /// component of `path` is checked
+    pub fn is_filtered_entry(&self, path: &Path, is_dir: bool) -> bool {
+        self.check(path, is_dir, true)
+    }
+
+    fn check(&self, path: &Path, is_dir: bool, last_only: bool) -> bool {
+        if self.symlinks == SymlinkPolicy::Follow
+            && self.hidden
+            && self.exclude.is_empty()
+            && !self.ignore_files
+            && self.denied.read().unwrap().is_empty()
+        {
+            return false;
+        }
+        // Paths outside of the root (followed symlinks) are not filtered
+        let rel = match path.strip_prefix(&self.root) {
+            Ok(rel) => rel,
+            Err(_) => return false,
+        };
+        let components = rel
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let start = if last_only {
+            components.len().saturating_sub(1)
+        } else {
+            0
+        };
+        let mut current = self.root.clone();
+        let mut current_rel = PathBuf::new();
+        for (i, name) in components.iter().enumerate() {
+            let parent = current.clone();
+            current.push(name);
+            current_rel.push(name);
+            if i < start {
+                continue;
+            }
+            let entry_is_dir = is_dir || i + 1 < components.len();
+            let name = name.to_string_lossy();
+            if !self.hidden && name.starts_with('.') {
+                return true;
+            }
+            if self
+                .exclude
+                .iter()
+                .any(|pattern| pattern.matches(&name) || pattern.matches_path(&current_rel))
+            {
+                return true;
+            }
+            if self.ignore_files && self.is_ignored(&parent, &current, entry_is_dir) {
+                return true;
+            }
+            if self.is_denied(&current) || !self.is_link_allowed(&current) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether `path` may be served when it is a symbolic link, links to denied
+    /// files never are
+    fn is_link_allowed(&self, path: &Path) -> bool {
+        if self.symlinks == SymlinkPolicy::Follow && self.denied.read().unwrap().is_empty() {
+            return true;
+        }
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => match self.symlinks {
+                SymlinkPolicy::Follow => path
+                    .canonicalize()
+                    .map_or(true, |target| !self.is_denied(&target)),
+                // Verify the resolved target (through all links), dangling links are refused
+                SymlinkPolicy::WithinRoot => path
+                    .canonicalize()
+                    .map(|target| target.starts_with(&self.root) && !self.is_denied(&target))
+                    .unwrap_or(false),
+                SymlinkPolicy::Deny => false,
+            },
+            _ => true,
+        }
+    }
+
+    /// Match `path` against the ignore files of `dir` and its parents up to the root
+    fn is_ignored(&self, dir: &Path, path: &Path, is_dir: bool) -> bool {
+        for ancestor in dir.ancestors() {
+            if !ancestor.starts_with(&self.root) {
+                break;
+            }
+            if let Some(matcher) = self.rules(ancestor) {
+                let matched = matcher.matched(path, is_dir);
+                if matched.is_ignore() {
+                    return true;
+                } else if matched.is_whitelist() {
+                    return false;
+                }
+            }
+        }
+        false
+    }
+
+    fn rules(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        let modified = IGNORE_FILES
+            .iter()
+            .map(|name| {
+                fs::metadata(dir.join(name))
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        let mut rules = self.rules.lock().unwrap();
+        if let Some(cached) = rules.get(dir) {
+            if cached.modified == modified {
+                return cached.matcher.clone();
+            }
+        }
+        let matcher = if modified.iter().any(Option::is_some) {
+            let mut builder = GitignoreBuilder::new(dir);
+            for name in &IGNORE_FILES {
+                let path = dir.join(name);
+                if path.is_file() {
+                    // Invalid lines are skipped, the valid ones still apply
+                    let _ = builder.add(path);
+                }
+            }
+            builder.build().ok().map(Arc::new)
+        } else {
+            None
+        };
+        rules.insert(
+            dir.to_path_buf(),
+            IgnoreRules {
+                modified,
+                matcher: matcher.clone(),
+            },
+        );
+        matcher
+    }
+}
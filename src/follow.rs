@@ -0,0 +1,347 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use htmlescape::encode_minimal;
+use iron::response::WriteBody;
+use iron::status;
+use iron::{IronError, IronResult, Request};
+use mime_guess as mime_types;
+
+use crate::listing;
+use crate::util::StringError;
+
+/// Largest `?lines=N`
+pub const MAX_FOLLOW_LINES: usize = 10000;
+/// The initial tail never reaches further back than this
+const MAX_TAIL_SIZE: u64 = 1024 * 1024;
+/// Appended data is sent in events of at most this size
+const CHUNK_SIZE: usize = 64 * 1024;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Comments sent on idle streams, which also notice gone clients
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// Reconnection delay of the browser, in milliseconds
+const RETRY: u64 = 2000;
+
+const SCRIPT: &str = include_str!("assets/follow.js");
+
+/// Whether `path` can be followed: text files, and files of unknown type such
+/// as rotated logs (`app.log.1`)
+pub fn is_followable(path: &Path) -> bool {
+    mime_types::from_path(path).first().is_none_or(|mime| {
+        mime.type_() == "text" || matches!(mime.subtype().as_str(), "json" | "xml" | "javascript")
+    })
+}
+
+/// `?follow` for the viewer page, `?follow=events` for the event stream
+pub fn is_event_stream(req: &Request) -> IronResult<bool> {
+    match req.url.as_ref().query_pairs().find(|(k, _)| k == "follow") {
+        Some((_, v)) if v == "events" => Ok(true),
+        Some((_, v)) if v.is_empty() => Ok(false),
+        Some((_, v)) => Err(IronError::new(
+            StringError(format!("Unknown follow: {} (events expected)", v)),
+            status::BadRequest,
+        )),
+        None => Ok(false),
+    }
+}
+
+/// `?lines=N`, the lines of the initial tail
+pub fn tail_lines(req: &Request, default: usize) -> IronResult<usize> {
+    match req.url.as_ref().query_pairs().find(|(k, _)| k == "lines") {
+        Some((_, v)) => match v.parse::<usize>() {
+            Ok(lines) if lines <= MAX_FOLLOW_LINES => Ok(lines),
+            _ => Err(IronError::new(
+                StringError(format!(
+                    "Invalid lines: {} (0 to {} expected)",
+                    v, MAX_FOLLOW_LINES
+                )),
+                status::BadRequest,
+            )),
+        },
+        None => Ok(default),
+    }
+}
+
+/// Followers of files (`--follow-lines`, `--follow-max`)
+///
+/// Every event stream holds a worker thread while it is open, so they are
+/// limited to keep some threads for the other requests.
+pub struct Follow {
+    /// Default of `?lines=N`
+    pub lines: usize,
+    pub max_streams: usize,
+    streams: Arc<AtomicUsize>,
+}
+
+impl Follow {
+    pub fn new(lines: usize, max_streams: usize) -> Follow {
+        Follow {
+            lines,
+            max_streams,
+            streams: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Event stream of the file at `path`, `None` when too many are open
+    ///
+    /// The stream starts at `resume` (the `Last-Event-ID` of a reconnecting
+    /// browser), or else at the last `lines` lines.
+    pub fn stream(&self, path: &Path, lines: usize, resume: Option<u64>) -> Option<FollowBody> {
+        let max_streams = self.max_streams;
+        let acquired = self
+            .streams
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                if n < max_streams {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok();
+        if !acquired {
+            return None;
+        }
+        Some(FollowBody {
+            path: path.to_owned(),
+            lines,
+            resume,
+            streams: Arc::clone(&self.streams),
+        })
+    }
+}
+
+/// Server-Sent Events of the data appended to a file
+///
+/// Data events carry the text, with the offset after it as their id. A
+/// `reset` event is sent when the file is truncated or replaced (log
+/// rotation), with id 0 as the data that follows starts at its beginning.
+pub struct FollowBody {
+    path: PathBuf,
+    lines: usize,
+    resume: Option<u64>,
+    streams: Arc<AtomicUsize>,
+}
+
+impl Drop for FollowBody {
+    fn drop(&mut self) {
+        self.streams.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl WriteBody for FollowBody {
+    fn write_body(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        let mut file = File::open(&self.path)?;
+        let mut identity = file_identity(&file.metadata()?);
+        let len = file.metadata()?.len();
+        let mut offset = match self.resume {
+            Some(offset) if offset <= len => offset,
+            Some(_) => {
+                send_event(w, "reset", "truncated", Some(0))?;
+                0
+            }
+            None => tail_offset(&mut file, len, self.lines)?,
+        };
+        write!(w, "retry: {}\nid: {}\n\n", RETRY, offset)?;
+        w.flush()?;
+        // Bytes of a character cut by the end of the last read
+        let mut pending = Vec::new();
+        let mut idle = Instant::now();
+        loop {
+            let len = file.metadata()?.len();
+            if len < offset {
+                pending.clear();
+                offset = 0;
+                send_event(w, "reset", "truncated", Some(0))?;
+            }
+            if len > offset {
+                offset = send_appended(w, &mut file, offset, len, &mut pending)?;
+                idle = Instant::now();
+            } else if let Ok(metadata) = fs::metadata(&self.path) {
+                // Replaced once the old file is read to its end
+                let current = file_identity(&metadata);
+                if current != identity {
+                    if let Ok(reopened) = File::open(&self.path) {
+                        file = reopened;
+                        identity = current;
+                        pending.clear();
+                        offset = 0;
+                        send_event(w, "reset", "rotated", Some(0))?;
+                        continue;
+                    }
+                }
+            }
+            if idle.elapsed() >= KEEPALIVE_INTERVAL {
+                w.write_all(b":\n\n")?;
+                w.flush()?;
+                idle = Instant::now();
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Send the bytes of `file` from `offset` to `len`, returns the new offset
+fn send_appended(
+    w: &mut dyn Write,
+    file: &mut File,
+    mut offset: u64,
+    len: u64,
+    pending: &mut Vec<u8>,
+) -> io::Result<u64> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0; CHUNK_SIZE];
+    while offset < len {
+        let want = CHUNK_SIZE.min((len - offset) as usize);
+        let read = file.read(&mut buf[..want])?;
+        if read == 0 {
+            break;
+        }
+        offset += read as u64;
+        pending.extend_from_slice(&buf[..read]);
+        let mut valid = match std::str::from_utf8(pending) {
+            // Incomplete character at the end, kept for the next read
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => pending.len(),
+        };
+        // A `\r` may be the first half of a `\r\n` line break
+        if offset < len && valid > 0 && pending[valid - 1] == b'\r' {
+            valid -= 1;
+        }
+        let text = String::from_utf8_lossy(&pending[..valid]).into_owned();
+        pending.drain(..valid);
+        if !text.is_empty() {
+            send_event(w, "message", &text, Some(offset - pending.len() as u64))?;
+        }
+    }
+    Ok(offset)
+}
+
+/// One event, every line of `data` in its own field
+fn send_event(w: &mut dyn Write, event: &str, data: &str, id: Option<u64>) -> io::Result<()> {
+    let mut message = String::with_capacity(data.len() + 32);
+    if event != "message" {
+        message.push_str("event: ");
+        message.push_str(event);
+        message.push('\n');
+    }
+    if let Some(id) = id {
+        message.push_str(&format!("id: {}\n", id));
+    }
+    // `\r` also ends lines in event streams
+    for line in data.replace("\r\n", "\n").split(['\n', '\r']) {
+        message.push_str("data: ");
+        message.push_str(line);
+        message.push('\n');
+    }
+    message.push('\n');
+    w.write_all(message.as_bytes())?;
+    w.flush()
+}
+
+/// Offset of the last `lines` lines of `file`, a final newline ending the
+/// last one
+fn tail_offset(file: &mut File, len: u64, lines: usize) -> io::Result<u64> {
+    if lines == 0 {
+        return Ok(len);
+    }
+    let limit = len.saturating_sub(MAX_TAIL_SIZE);
+    let mut buf = vec![0; 8192];
+    let mut end = len;
+    // The newline ending the file does not start a line
+    let mut newlines = 0;
+    let mut skip_last = true;
+    while end > limit {
+        let start = end.saturating_sub(buf.len() as u64).max(limit);
+        let chunk = &mut buf[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        for (i, byte) in chunk.iter().enumerate().rev() {
+            if *byte != b'\n' {
+                skip_last = false;
+                continue;
+            }
+            if skip_last {
+                skip_last = false;
+                continue;
+            }
+            newlines += 1;
+            if newlines == lines {
+                return Ok(start + i as u64 + 1);
+            }
+        }
+        end = start;
+    }
+    if limit == 0 {
+        return Ok(0);
+    }
+    // Lines cut at `MAX_TAIL_SIZE`, start after the first newline within it
+    file.seek(SeekFrom::Start(limit))?;
+    let mut head = Vec::new();
+    (&mut *file).take(MAX_TAIL_SIZE).read_to_end(&mut head)?;
+    Ok(head
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map_or(len, |i| limit + i as u64 + 1))
+}
+
+#[cfg(unix)]
+fn file_identity(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Rotation is only noticed through truncation
+#[cfg(not(unix))]
+fn file_identity(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Viewer page of the event stream of the file named `title`
+pub fn render(title: &str, raw_url: &str, dir_url: &str, lines: usize) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width,initial-scale=1.0"/>
+  <meta name="color-scheme" content="light dark"/>
+  <title>{title}</title>
+  <style>{style}</style>
+</head>
+<body>
+<header>
+  <nav class="breadcrumb" aria-label="Path">{title}</nav>
+  <div class="toolbar">
+    <a href="{dir_url}">Parent directory</a>
+    <a href="{raw_url}">Raw</a>
+    <form method="get">
+      <input type="hidden" name="follow">
+      <label>Lines <input type="number" name="lines" min="0" max="{max_lines}" value="{lines}"></label>
+      <button type="submit">Reload</button>
+    </form>
+    <label><input type="checkbox" id="autoscroll" checked> Scroll to the end</label>
+    <span id="follow-status" role="status">Connecting&hellip;</span>
+  </div>
+</header>
+<main class="follow">
+  <pre id="log" data-src="{events_url}"></pre>
+</main>
+<script>{script}</script>
+</body>
+</html>
+"#,
+        title = encode_minimal(title),
+        style = listing::STYLE,
+        dir_url = encode_minimal(dir_url),
+        raw_url = encode_minimal(raw_url),
+        max_lines = MAX_FOLLOW_LINES,
+        lines = lines,
+        events_url = encode_minimal(&format!("{}?follow=events&lines={}", raw_url, lines)),
+        script = SCRIPT,
+    )
+}
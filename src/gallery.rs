@@ -0,0 +1,264 @@
+use std::env;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+use filetime::FileTime;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use iron::status;
+use iron::{IronError, IronResult, Request};
+use sha2::{Digest, Sha256};
+
+use crate::util::{hex_string, os_str_bytes, StringError};
+
+/// Width of the thumbnails shown by `?view=gallery`
+pub const GALLERY_THUMB_WIDTH: u32 = 320;
+/// Widths thumbnails are made in, `?thumb=WIDTH` is rounded up to one of them
+const THUMB_WIDTHS: [u32; 4] = [160, GALLERY_THUMB_WIDTH, 640, 1280];
+/// Share of `--thumbnail-cache-size` left after an eviction
+const EVICTED_SIZE_RATIO: u64 = 90;
+
+pub const SCRIPT: &str = include_str!("assets/gallery.js");
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "gif", "webp"];
+
+/// Numbers the temporary files of thumbnails being written
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether thumbnails can be made of `path`, by its extension
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+/// `?view=gallery` or `?view=list` (the default)
+pub fn is_gallery_view(req: &Request) -> IronResult<bool> {
+    match req.url.as_ref().query_pairs().find(|(k, _)| k == "view") {
+        Some((_, v)) if v == "gallery" => Ok(true),
+        Some((_, v)) if v == "list" => Ok(false),
+        Some((_, v)) => Err(IronError::new(
+            StringError(format!("Unknown view: {} (gallery or list expected)", v)),
+            status::BadRequest,
+        )),
+        None => Ok(false),
+    }
+}
+
+/// `?thumb=WIDTH`, rounded up to one of the `THUMB_WIDTHS`
+pub fn thumb_width(req: &Request) -> IronResult<Option<u32>> {
+    match req.url.as_ref().query_pairs().find(|(k, _)| k == "thumb") {
+        Some((_, v)) => match v.parse::<u32>() {
+            Ok(width) if width > 0 => Ok(Some(
+                THUMB_WIDTHS
+                    .iter()
+                    .copied()
+                    .find(|bucket| *bucket >= width)
+                    .unwrap_or(THUMB_WIDTHS[THUMB_WIDTHS.len() - 1]),
+            )),
+            _ => Err(IronError::new(
+                StringError(format!("Invalid thumb: {} (a width expected)", v)),
+                status::BadRequest,
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Default of `--thumbnail-cache`, private to the user running the server
+pub fn default_cache_dir() -> PathBuf {
+    #[cfg(unix)]
+    let name = format!("simple-http-server-thumbnails-{}", unsafe {
+        libc::geteuid()
+    });
+    #[cfg(not(unix))]
+    let name = "simple-http-server-thumbnails".to_owned();
+    env::temp_dir().join(name)
+}
+
+/// Thumbnails cached on disk (`--thumbnail-cache`), the least recently used
+/// ones are removed past `--thumbnail-cache-size`
+pub struct Thumbnails {
+    dir: PathBuf,
+    max_size: u64,
+    /// Total size of the cached thumbnails
+    size: AtomicU64,
+    evicting: AtomicBool,
+}
+
+impl Thumbnails {
+    /// Cache in `dir`, created when missing
+    ///
+    /// The directory is refused when another user could have put files in it,
+    /// they would be sent as thumbnails.
+    pub fn open(dir: PathBuf, max_size: u64) -> io::Result<Thumbnails> {
+        let thumbnails = Thumbnails {
+            dir,
+            max_size,
+            size: AtomicU64::new(0),
+            evicting: AtomicBool::new(false),
+        };
+        thumbnails.create_dir()?;
+        thumbnails.evict(None)?;
+        Ok(thumbnails)
+    }
+
+    #[cfg(unix)]
+    fn create_dir(&self) -> io::Result<()> {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+        if let Err(err) = fs::DirBuilder::new().mode(0o700).create(&self.dir) {
+            if err.kind() != io::ErrorKind::AlreadyExists {
+                return Err(err);
+            }
+        }
+        // Not following symbolic links, the directory itself is checked
+        let metadata = fs::symlink_metadata(&self.dir)?;
+        let refused = if !metadata.is_dir() {
+            Some("not a directory")
+        } else if metadata.uid() != unsafe { libc::geteuid() } {
+            Some("owned by another user")
+        } else if metadata.mode() & 0o022 != 0 {
+            Some("writable by other users")
+        } else {
+            None
+        };
+        match refused {
+            Some(reason) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{}: {}", self.dir.display(), reason),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn create_dir(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)
+    }
+
+    /// Cached thumbnail of the image at `path`, fitting in `width` x `width`, made
+    /// when missing
+    ///
+    /// Thumbnails are keyed by path, modification time and size of the image, a
+    /// changed image gets a new one. Images that cannot be decoded fail with
+    /// `io::ErrorKind::InvalidData`.
+    pub fn get(&self, path: &Path, width: u32) -> io::Result<PathBuf> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut hasher = Sha256::new();
+        hasher.update(os_str_bytes(path.as_os_str()));
+        hasher.update(modified.as_nanos().to_le_bytes());
+        hasher.update(metadata.len().to_le_bytes());
+        hasher.update(width.to_le_bytes());
+        let key = hex_string(&hasher.finalize());
+        for ext in &["jpg", "png"] {
+            let cached = self.dir.join(format!("{}.{}", key, ext));
+            if cached.is_file() {
+                // Recently used, evicted last. The access time is changed, not
+                // the modification time the `Last-Modified` and `ETag` come from
+                let _ = filetime::set_file_atime(&cached, FileTime::now());
+                return Ok(cached);
+            }
+        }
+
+        let (data, ext) = make_thumbnail(path, width)?;
+        // Also when removed by a cleaner of the temporary directory since
+        self.create_dir()?;
+        let cached = self.dir.join(format!("{}.{}", key, ext));
+        // Written aside then renamed, so a partial thumbnail is never served
+        let pending = self.dir.join(format!(
+            "{}.{}.{}",
+            key,
+            std::process::id(),
+            PENDING.fetch_add(1, Ordering::Relaxed)
+        ));
+        let len = data.len() as u64;
+        fs::write(&pending, data)?;
+        if let Err(err) = fs::rename(&pending, &cached) {
+            let _ = fs::remove_file(&pending);
+            return Err(err);
+        }
+        if self.size.fetch_add(len, Ordering::Relaxed) + len > self.max_size {
+            self.evict(Some(&cached))?;
+        }
+        Ok(cached)
+    }
+
+    /// Recount the cached thumbnails and remove the least recently used ones
+    /// when they are over `max_size`, except `keep`
+    fn evict(&self, keep: Option<&Path>) -> io::Result<()> {
+        if self.evicting.swap(true, Ordering::Acquire) {
+            return Ok(());
+        }
+        let result = (|| {
+            let mut thumbnails = Vec::new();
+            for entry in fs::read_dir(&self.dir)?.flatten() {
+                let path = entry.path();
+                // Thumbnails being written are left alone
+                let cached = path
+                    .extension()
+                    .is_some_and(|ext| ext == "jpg" || ext == "png");
+                match entry.metadata() {
+                    Ok(metadata) if cached && metadata.is_file() => {
+                        let used = metadata.accessed().unwrap_or(UNIX_EPOCH);
+                        thumbnails.push((used, metadata.len(), path));
+                    }
+                    _ => {}
+                }
+            }
+            let mut size: u64 = thumbnails.iter().map(|(_, len, _)| len).sum();
+            if size > self.max_size {
+                let target = self.max_size / 100 * EVICTED_SIZE_RATIO;
+                thumbnails.sort();
+                for (_, len, path) in thumbnails {
+                    if size <= target {
+                        break;
+                    }
+                    if Some(path.as_path()) != keep && fs::remove_file(&path).is_ok() {
+                        size -= len;
+                    }
+                }
+            }
+            self.size.store(size, Ordering::Relaxed);
+            Ok(())
+        })();
+        self.evicting.store(false, Ordering::Release);
+        result
+    }
+}
+
+/// Encoded thumbnail and its extension: PNG when the image has transparency,
+/// JPEG otherwise
+fn make_thumbnail(path: &Path, width: u32) -> io::Result<(Vec<u8>, &'static str)> {
+    let invalid = |err: image::ImageError| io::Error::new(io::ErrorKind::InvalidData, err);
+    let mut decoder = ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_decoder()
+        .map_err(invalid)?;
+    let orientation = decoder.orientation().map_err(invalid)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+    image.apply_orientation(orientation);
+    // Never enlarged
+    if image.width() > width || image.height() > width {
+        image = image.thumbnail(width, width);
+    }
+    let mut data = Cursor::new(Vec::new());
+    let ext = if image.color().has_alpha() {
+        image
+            .write_to(&mut data, ImageFormat::Png)
+            .map_err(invalid)?;
+        "png"
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut data, ImageFormat::Jpeg)
+            .map_err(invalid)?;
+        "jpg"
+    };
+    Ok((data.into_inner(), ext))
+}
@@ -0,0 +1,850 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+use htmlescape::encode_minimal;
+use iron::headers::{Accept, ContentType};
+use iron::response::WriteBody;
+use iron::status;
+use iron::{IronError, IronResult, Request, Response};
+use mime_guess as mime_types;
+use pretty_bytes::converter::convert;
+use serde::Serialize;
+
+use crate::columns::{Column, ColumnValues, Columns};
+use crate::filter::EntryFilter;
+use crate::gallery::{self, GALLERY_THUMB_WIDTH};
+use crate::player;
+use crate::preview::Preview;
+use crate::search::SEARCH_TIMEOUT;
+use crate::util::{display_path, encode_link_path, system_time_to_date_time, StringError};
+
+/// Assets of the built-in HTML listing, embedded so the page makes no other request
+pub const STYLE: &str = include_str!("assets/listing.css");
+pub const SCRIPT: &str = include_str!("assets/listing.js");
+/// SVG sprite, `<use href="#icon-{name}"/>` with the names of `Entry::icon`
+pub const ICONS: &str = include_str!("assets/icons.svg");
+
+/// Extensions shown with the archive and source code icons
+const ARCHIVE_EXTENSIONS: [&str; 11] = [
+    "zip", "tar", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "jar", "deb",
+];
+const CODE_EXTENSIONS: [&str; 22] = [
+    "rs", "c", "h", "cc", "cpp", "hpp", "go", "java", "kt", "py", "rb", "js", "ts", "php", "sh",
+    "html", "css", "json", "toml", "yaml", "yml", "xml",
+];
+
+/// Default and maximum number of entries in an Atom feed (`?entries=N`)
+const FEED_LIMIT: usize = 20;
+const MAX_FEED_LIMIT: usize = 1000;
+/// Entries a feed looks at, at most
+const MAX_FEED_WALK: usize = 100_000;
+
+/// One entry of a directory (or archive) listing
+pub struct Entry {
+    /// Lossy when the name is not valid UTF-8, see `os_filename`
+    pub filename: String,
+    /// Exact name (or relative path) when it is not valid UTF-8, used in links
+    pub os_filename: Option<OsString>,
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: SystemTime,
+    /// Target of a symbolic link
+    pub symlink: Option<PathBuf>,
+    /// Location on disk, `None` for archive members
+    pub path: Option<PathBuf>,
+    /// Optional columns, filled in for the entries shown only
+    pub columns: ColumnValues,
+}
+
+impl Entry {
+    /// Display name and, when it is not valid UTF-8, exact name of an entry
+    pub fn split_name(name: OsString) -> (String, Option<OsString>) {
+        match name.into_string() {
+            Ok(name) => (name, None),
+            Err(name) => (name.to_string_lossy().into_owned(), Some(name)),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        if self.symlink.is_some() {
+            "symlink"
+        } else if self.is_dir {
+            "directory"
+        } else {
+            "file"
+        }
+    }
+
+    /// Size of a file, or recursive size of a directory once computed
+    pub fn total_size(&self) -> u64 {
+        if self.is_dir {
+            self.columns.dir_size.unwrap_or(0)
+        } else {
+            self.len
+        }
+    }
+
+    pub fn mime(&self) -> Option<String> {
+        if self.is_dir {
+            None
+        } else {
+            Some(
+                mime_types::from_path(&self.filename)
+                    .first_or_octet_stream()
+                    .to_string(),
+            )
+        }
+    }
+
+    /// Lowercase extension of a file, `None` for directories
+    pub fn extension(&self) -> Option<String> {
+        if self.is_dir {
+            return None;
+        }
+        Path::new(&self.filename)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+    }
+
+    /// Icon of the SVG sprite (`ICONS`)
+    pub fn icon(&self) -> &'static str {
+        if self.is_dir {
+            return "folder";
+        }
+        if self.symlink.is_some() {
+            return "link";
+        }
+        let extension = self.extension().unwrap_or_default();
+        if ARCHIVE_EXTENSIONS.contains(&extension.as_str()) {
+            return "archive";
+        }
+        if CODE_EXTENSIONS.contains(&extension.as_str()) {
+            return "code";
+        }
+        let mime = self.mime().unwrap_or_default();
+        match mime.split('/').next().unwrap_or("") {
+            "image" => "image",
+            "audio" => "audio",
+            "video" => "video",
+            "text" => "text",
+            _ if mime == "application/pdf" => "pdf",
+            _ => "file",
+        }
+    }
+
+    /// Absolute link to the entry, `filename` may be a relative path like `a/b.txt`
+    pub fn href(&self, base_url: &str, path_prefix: &[OsString]) -> String {
+        let mut link = path_prefix.to_owned();
+        match self.os_filename {
+            Some(ref name) => link.extend(Path::new(name).iter().map(|s| s.to_owned())),
+            None => link.extend(self.filename.split('/').map(OsString::from)),
+        }
+        if self.is_dir {
+            link.push(OsString::new());
+        }
+        format!("{}{}", base_url, encode_link_path(&link))
+    }
+}
+
+/// Representation of a directory listing
+#[derive(Clone, Copy, PartialEq)]
+pub enum ListingFormat {
+    Html,
+    Json,
+    /// One absolute URL per line (`wget -i` input)
+    Text,
+    Csv,
+    /// Feed of the most recently modified files, recursively
+    Atom,
+}
+
+impl ListingFormat {
+    /// (query parameter value, media type) of every format
+    const MEDIA_TYPES: [(ListingFormat, &'static str, &'static str); 5] = [
+        (ListingFormat::Html, "html", "text/html"),
+        (ListingFormat::Json, "json", "application/json"),
+        (ListingFormat::Text, "txt", "text/plain"),
+        (ListingFormat::Csv, "csv", "text/csv"),
+        (ListingFormat::Atom, "atom", "application/atom+xml"),
+    ];
+
+    /// Pick the format from the `?format=` query parameter, or else the `Accept` header
+    pub fn from_request(req: &Request) -> IronResult<ListingFormat> {
+        for (k, v) in req.url.as_ref().query_pairs() {
+            if k == "format" {
+                return match Self::MEDIA_TYPES.iter().find(|(_, name, _)| *name == v) {
+                    Some((format, _, _)) => Ok(*format),
+                    None => Err(IronError::new(
+                        StringError(format!("Unknown listing format: {}", v)),
+                        status::BadRequest,
+                    )),
+                };
+            }
+        }
+        if let Some(Accept(items)) = req.headers.get::<Accept>() {
+            let mut items: Vec<_> = items.iter().filter(|item| item.quality.0 > 0).collect();
+            // Stable sort: equal qualities keep the client's order
+            items.sort_by_key(|item| Reverse(item.quality));
+            // Browsers always accept text/html, prefer it over anything else
+            let accepts_html = items
+                .iter()
+                .any(|item| format!("{}/{}", item.item.0, item.item.1) == "text/html");
+            if !accepts_html {
+                for item in items {
+                    let media_type = format!("{}/{}", item.item.0, item.item.1);
+                    if let Some((format, _, _)) = Self::MEDIA_TYPES
+                        .iter()
+                        .find(|(_, _, type_)| *type_ == media_type)
+                    {
+                        return Ok(*format);
+                    }
+                }
+            }
+        }
+        Ok(ListingFormat::Html)
+    }
+}
+
+/// Requested page of a listing: `?page=N&per_page=M` (1-based)
+pub struct Page {
+    pub page: usize,
+    pub per_page: usize,
+}
+
+pub const MAX_PER_PAGE: usize = 10_000;
+/// Entries per page without `?per_page=`
+pub const PER_PAGE: usize = 100;
+
+impl Page {
+    /// `None` when neither `page` nor `per_page` is given
+    pub fn from_request(req: &Request, default_per_page: usize) -> IronResult<Option<Page>> {
+        let mut page = None;
+        let mut per_page = None;
+        for (k, v) in req.url.as_ref().query_pairs() {
+            let value = if k == "page" {
+                &mut page
+            } else if k == "per_page" {
+                &mut per_page
+            } else {
+                continue;
+            };
+            match v.parse::<usize>() {
+                Ok(n) if n > 0 => *value = Some(n),
+                _ => {
+                    return Err(IronError::new(
+                        StringError(format!("Invalid {}: {}", k, v)),
+                        status::BadRequest,
+                    ))
+                }
+            }
+        }
+        match (page, per_page) {
+            (None, None) => Ok(None),
+            (_, Some(per_page)) if per_page > MAX_PER_PAGE => Err(IronError::new(
+                StringError(format!("per_page must not exceed {}", MAX_PER_PAGE)),
+                status::BadRequest,
+            )),
+            (page, per_page) => Ok(Some(Page {
+                page: page.unwrap_or(1),
+                per_page: per_page.unwrap_or(default_per_page),
+            })),
+        }
+    }
+
+    /// Index range of the page in a listing of `total` entries
+    pub fn range(&self, total: usize) -> std::ops::Range<usize> {
+        let start = (self.page - 1).saturating_mul(self.per_page).min(total);
+        start..(start + self.per_page).min(total)
+    }
+
+    /// Navigation between the pages of a listing of `total` entries
+    pub fn pagination(&self, req: &Request, total: usize) -> Pagination {
+        let pages = total.div_ceil(self.per_page).max(1);
+        Pagination {
+            page: self.page,
+            per_page: self.per_page,
+            pages,
+            total,
+            prev: if self.page > 1 {
+                Some(page_href(req, (self.page - 1).min(pages)))
+            } else {
+                None
+            },
+            next: if self.page < pages {
+                Some(page_href(req, self.page + 1))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Pagination {
+    pub page: usize,
+    pub per_page: usize,
+    pub pages: usize,
+    pub total: usize,
+    /// Relative links (`?...&page=N`) keeping the other query parameters
+    pub prev: Option<String>,
+    pub next: Option<String>,
+}
+
+/// Link to another page of the requested listing
+fn page_href(req: &Request, page: usize) -> String {
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    for (k, v) in req.url.as_ref().query_pairs() {
+        if k != "page" {
+            query.append_pair(&k, &v);
+        }
+    }
+    query.append_pair("page", &page.to_string());
+    format!("?{}", query.finish())
+}
+
+/// Entries of a directory, read and described one at a time
+///
+/// Filtered entries (and links refused by the `--symlinks` policy) are skipped,
+/// the `--columns` values are filled in when given.
+pub struct DirectoryRows {
+    read_dir: fs::ReadDir,
+    filter: Arc<EntryFilter>,
+    columns: Option<Arc<Columns>>,
+}
+
+impl DirectoryRows {
+    pub fn new(
+        dir: &Path,
+        filter: Arc<EntryFilter>,
+        columns: Option<Arc<Columns>>,
+    ) -> io::Result<DirectoryRows> {
+        Ok(DirectoryRows {
+            read_dir: fs::read_dir(dir)?,
+            filter,
+            columns,
+        })
+    }
+}
+
+impl Iterator for DirectoryRows {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<io::Result<Entry>> {
+        loop {
+            let entry = match self.read_dir.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            let mut metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => return Some(Err(e)),
+            };
+            let symlink = if metadata.file_type().is_symlink() {
+                // Describe the target, or the link itself when dangling
+                if let Ok(target_metadata) = fs::metadata(entry.path()) {
+                    metadata = target_metadata;
+                }
+                fs::read_link(entry.path()).ok()
+            } else {
+                None
+            };
+            let path = entry.path();
+            if self.filter.is_filtered_entry(&path, metadata.is_dir()) {
+                continue;
+            }
+            let modified = match metadata.modified() {
+                Ok(modified) => modified,
+                Err(e) => return Some(Err(e)),
+            };
+            let columns = match self.columns {
+                Some(ref columns) => columns.values(&path, metadata.is_dir(), &self.filter),
+                None => ColumnValues::default(),
+            };
+            let (filename, os_filename) = Entry::split_name(entry.file_name());
+            return Some(Ok(Entry {
+                filename,
+                os_filename,
+                is_dir: metadata.is_dir(),
+                len: metadata.len(),
+                modified,
+                symlink,
+                path: Some(path),
+                columns,
+            }));
+        }
+    }
+}
+
+/// Built-in HTML listing, written while it is sent
+///
+/// `head` is sent (and flushed) first. The rows of `entries` follow, already
+/// sorted and paginated, then those of `directory`, read from the disk only now
+/// (unsorted listings).
+pub struct HtmlListingBody {
+    /// Everything before the first row
+    pub head: String,
+    pub entries: Vec<Entry>,
+    /// Entries read while writing the rows, after `entries`
+    pub directory: Option<DirectoryRows>,
+    pub base_url: String,
+    pub path_prefix: Vec<OsString>,
+    /// Selection checkboxes of the archive download form
+    pub checkboxes: bool,
+    /// `?preview` and `?play` links of the files having one
+    pub previews: bool,
+    /// Thumbnail tiles instead of table rows (`?view=gallery`)
+    pub gallery: bool,
+    /// Optional columns, after the size
+    pub columns: Vec<Column>,
+    /// Everything after the last row
+    pub tail: String,
+}
+
+impl HtmlListingBody {
+    fn write_entry(&self, w: &mut dyn Write, entry: &Entry) -> io::Result<()> {
+        if self.gallery {
+            self.write_tile(w, entry)
+        } else {
+            self.write_row(w, entry)
+        }
+    }
+
+    fn write_tile(&self, w: &mut dyn Write, entry: &Entry) -> io::Result<()> {
+        let href = entry.href(&self.base_url, &self.path_prefix);
+        let name = encode_minimal(&entry.filename);
+        if entry.is_dir {
+            write!(
+                w,
+                r##"
+<li class="entry tile dir" data-name="{name}"><a class="entry-link" href="{href}?view=gallery"><svg class="icon folder" aria-hidden="true"><use href="#icon-folder"/></svg><span class="tile-name">{name}/</span></a></li>
+"##,
+                name = name,
+                href = href,
+            )
+        } else {
+            write!(
+                w,
+                r#"
+<li class="entry tile" data-name="{name}"><a class="entry-link image" href="{href}"><img src="{href}?thumb={width}" alt="" loading="lazy"/><span class="tile-name">{name}</span></a></li>
+"#,
+                name = name,
+                href = href,
+                width = GALLERY_THUMB_WIDTH,
+            )
+        }
+    }
+
+    fn write_row(&self, w: &mut dyn Write, entry: &Entry) -> io::Result<()> {
+        let file_modified = system_time_to_date_time(entry.modified)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        let file_size = if entry.is_dir {
+            "-".to_owned()
+        } else {
+            convert(entry.len as f64)
+        };
+        let file_name_label = if entry.is_dir {
+            format!("{}/", &entry.filename)
+        } else {
+            entry.filename.clone()
+        };
+        // Percent-encoded like links, names may not be valid UTF-8
+        let checkbox = if self.checkboxes {
+            let name = match entry.os_filename {
+                Some(ref name) => name.clone(),
+                None => OsString::from(&entry.filename),
+            };
+            format!(
+                r#"<input type="checkbox" name="select" value="{}" form="archive-form" aria-label="Select"/> "#,
+                encode_minimal(&encode_link_path(&[name]))
+            )
+        } else {
+            "".to_owned()
+        };
+        let link_target = match entry.symlink {
+            Some(ref target) => format!(
+                r#"<span class="target">&rarr; {}</span>"#,
+                encode_minimal(&target.to_string_lossy())
+            ),
+            None => "".to_owned(),
+        };
+        let href = entry.href(&self.base_url, &self.path_prefix);
+        let path = Path::new(&entry.filename);
+        let preview_link = if !self.previews || entry.is_dir {
+            "".to_owned()
+        } else if Preview::from_path(path).is_some() {
+            format!(r#"<a class="preview-link" href="{}?preview">Preview</a>"#, href)
+        } else if player::is_media(path) {
+            format!(r#"<a class="preview-link" href="{}?play">Play</a>"#, href)
+        } else {
+            "".to_owned()
+        };
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| {
+                let values = &entry.columns;
+                let value = match column {
+                    Column::Mode => values.mode.clone(),
+                    Column::Owner => values.owner.as_ref().map(|owner| match values.group {
+                        Some(ref group) => format!("{}:{}", owner, group),
+                        None => owner.clone(),
+                    }),
+                    Column::Mime => entry.mime(),
+                    Column::Items => values.items.map(|items| items.to_string()),
+                    Column::DirSize => values.dir_size.map(|size| convert(size as f64)),
+                };
+                format!(
+                    r#"
+  <td class="col {}">{}</td>"#,
+                    column.name(),
+                    encode_minimal(value.as_deref().unwrap_or("-"))
+                )
+            })
+            .collect::<String>();
+        write!(
+            w,
+            r##"
+<tr class="entry{dir_class}" data-name="{name}">
+  <td class="name">{checkbox}<svg class="icon {icon}" aria-hidden="true"><use href="#icon-{icon}"/></svg><a class="entry-link" href="{href}">{label}</a>{target}{preview}</td>
+  <td class="mtime">{modified}</td>
+  <td class="size">{filesize}</td>{columns}
+</tr>
+"##,
+            dir_class = if entry.is_dir { " dir" } else { "" },
+            name = encode_minimal(&entry.filename),
+            checkbox = checkbox,
+            icon = entry.icon(),
+            href = href,
+            label = encode_minimal(&file_name_label),
+            target = link_target,
+            preview = preview_link,
+            modified = file_modified,
+            filesize = file_size,
+            columns = columns,
+        )
+    }
+}
+
+impl WriteBody for HtmlListingBody {
+    fn write_body(&mut self, w: &mut dyn Write) -> io::Result<()> {
+        let mut w = io::BufWriter::new(w);
+        w.write_all(self.head.as_bytes())?;
+        w.flush()?;
+        for entry in &self.entries {
+            self.write_entry(&mut w, entry)?;
+        }
+        if let Some(directory) = self.directory.take() {
+            for entry in directory {
+                let entry = entry?;
+                // The gallery shows directories and images only
+                if self.gallery && !entry.is_dir && !gallery::is_image(Path::new(&entry.filename)) {
+                    continue;
+                }
+                self.write_entry(&mut w, &entry)?;
+            }
+        }
+        w.write_all(self.tail.as_bytes())?;
+        w.flush()
+    }
+}
+
+#[derive(Serialize)]
+struct JsonEntry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    size: u64,
+    mtime: String,
+    mime: Option<String>,
+    symlink_target: Option<String>,
+    href: String,
+    #[serde(flatten)]
+    columns: ColumnValues,
+}
+
+#[derive(Serialize)]
+struct JsonListing {
+    path: String,
+    sort: Option<String>,
+    order: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    search: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<bool>,
+    entries: Vec<JsonEntry>,
+}
+
+/// Render (sorted) entries as JSON
+pub fn json_response(
+    entries: Vec<Entry>,
+    path_prefix: &[OsString],
+    base_url: &str,
+    sorting: Option<(String, String)>,
+    page: Option<Page>,
+    search: Option<(String, bool)>,
+) -> Response {
+    let total = entries.len();
+    let range = match page {
+        Some(ref page) => page.range(total),
+        None => 0..total,
+    };
+    let mut current_link = path_prefix.to_owned();
+    current_link.push(OsString::new());
+    let listing = JsonListing {
+        path: format!("{}{}", base_url, encode_link_path(&current_link)),
+        sort: sorting.as_ref().map(|(field, _)| field.clone()),
+        order: sorting.map(|(_, order)| order),
+        page: page.as_ref().map(|page| page.page),
+        per_page: page.as_ref().map(|page| page.per_page),
+        total,
+        truncated: search.as_ref().map(|(_, truncated)| *truncated),
+        search: search.map(|(pattern, _)| pattern),
+        entries: entries
+            .into_iter()
+            .skip(range.start)
+            .take(range.len())
+            .map(|entry| JsonEntry {
+                kind: entry.kind(),
+                size: entry.len,
+                mtime: system_time_to_date_time(entry.modified).to_rfc3339(),
+                mime: entry.mime(),
+                symlink_target: entry
+                    .symlink
+                    .as_ref()
+                    .map(|target| target.to_string_lossy().to_string()),
+                href: entry.href(base_url, path_prefix),
+                name: entry.filename,
+                columns: entry.columns,
+            })
+            .collect(),
+    };
+    let mut resp = Response::with((status::Ok, serde_json::to_string(&listing).unwrap()));
+    resp.headers.set(ContentType::json());
+    resp
+}
+
+/// Absolute URL of `href` based on the request URL (Host header)
+fn absolute_url(req: &Request, href: &str) -> String {
+    match req.url.as_ref().join(href) {
+        Ok(url) => url.to_string(),
+        Err(_) => href.to_owned(),
+    }
+}
+
+/// Render (sorted) entries as `text/plain`, one absolute URL per line
+pub fn text_response(
+    req: &Request,
+    entries: Vec<Entry>,
+    path_prefix: &[OsString],
+    base_url: &str,
+    page: Option<Page>,
+) -> Response {
+    let range = match page {
+        Some(ref page) => page.range(entries.len()),
+        None => 0..entries.len(),
+    };
+    let mut body = String::new();
+    for entry in &entries[range] {
+        body.push_str(&absolute_url(req, &entry.href(base_url, path_prefix)));
+        body.push('\n');
+    }
+    let mut resp = Response::with((status::Ok, body));
+    resp.headers.set(ContentType::plaintext());
+    resp
+}
+
+/// Render (sorted) entries as CSV: name, size, mtime (directory names end with `/`)
+pub fn csv_response(entries: Vec<Entry>, page: Option<Page>) -> Response {
+    let range = match page {
+        Some(ref page) => page.range(entries.len()),
+        None => 0..entries.len(),
+    };
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::CRLF)
+        .from_writer(Vec::new());
+    // Records of three fields written to memory, which cannot fail
+    writer.write_record(["name", "size", "mtime"]).unwrap();
+    for entry in &entries[range] {
+        let name = if entry.is_dir {
+            format!("{}/", entry.filename)
+        } else {
+            entry.filename.clone()
+        };
+        writer
+            .write_record([
+                name,
+                entry.len.to_string(),
+                system_time_to_date_time(entry.modified).to_rfc3339(),
+            ])
+            .unwrap();
+    }
+    let body = writer.into_inner().unwrap();
+    let mut resp = Response::with((status::Ok, body));
+    resp.headers
+        .set_raw("content-type", vec![b"text/csv; charset=utf-8".to_vec()]);
+    resp
+}
+
+/// Number of feed entries from the `?entries=` query parameter
+pub fn feed_limit(req: &Request) -> IronResult<usize> {
+    for (k, v) in req.url.as_ref().query_pairs() {
+        if k == "entries" {
+            return match v.parse::<usize>() {
+                Ok(n) if n > 0 && n <= MAX_FEED_LIMIT => Ok(n),
+                _ => Err(IronError::new(
+                    StringError(format!(
+                        "Invalid entries: {} (1 to {} expected)",
+                        v, MAX_FEED_LIMIT
+                    )),
+                    status::BadRequest,
+                )),
+            };
+        }
+    }
+    Ok(FEED_LIMIT)
+}
+
+/// The `limit` most recently modified files under `dir` down to `max_depth`,
+/// symbolic links are not followed
+///
+/// Like searches, the walk stops after `SEARCH_TIMEOUT` or `MAX_FEED_WALK`
+/// entries and the feed is made of the files seen until then.
+pub fn recent_files(
+    dir: &Path,
+    limit: usize,
+    max_depth: Option<usize>,
+    filter: &EntryFilter,
+) -> io::Result<Vec<Entry>> {
+    let deadline = Instant::now() + SEARCH_TIMEOUT;
+    let mut walked = 0;
+    // Min-heap of the newest files seen so far
+    let mut heap = BinaryHeap::new();
+    let mut pending = vec![(dir.to_path_buf(), PathBuf::new(), 1)];
+    'walk: while let Some((path, prefix, depth)) = pending.pop() {
+        let read_dir = match fs::read_dir(&path) {
+            Ok(read_dir) => read_dir,
+            // Only the top directory must be readable
+            Err(_) if !prefix.as_os_str().is_empty() => continue,
+            Err(err) => return Err(err),
+        };
+        for entry in read_dir.flatten() {
+            walked += 1;
+            if walked > MAX_FEED_WALK || Instant::now() > deadline {
+                break 'walk;
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                _ => continue,
+            };
+            if filter.is_filtered_entry(&entry.path(), metadata.is_dir()) {
+                continue;
+            }
+            let name = prefix.join(entry.file_name());
+            if metadata.is_dir() {
+                if !matches!(max_depth, Some(max) if depth >= max) {
+                    pending.push((entry.path(), name, depth + 1));
+                }
+            } else if metadata.is_file() {
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                heap.push(Reverse((modified, name, metadata.len())));
+                if heap.len() > limit {
+                    heap.pop();
+                }
+            }
+        }
+    }
+    Ok(heap
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((modified, name, len))| {
+            let path = dir.join(&name);
+            let (filename, os_filename) = Entry::split_name(name.into_os_string());
+            Entry {
+                filename,
+                os_filename,
+                is_dir: false,
+                len,
+                modified,
+                symlink: None,
+                path: Some(path),
+                columns: ColumnValues::default(),
+            }
+        })
+        .collect())
+}
+
+/// Render an Atom feed of the `limit` most recently modified files among `entries`
+pub fn atom_response(
+    req: &Request,
+    mut entries: Vec<Entry>,
+    path_prefix: &[OsString],
+    base_url: &str,
+    limit: usize,
+) -> Response {
+    entries.retain(|entry| !entry.is_dir);
+    entries.sort_by_key(|entry| Reverse(entry.modified));
+    entries.truncate(limit);
+
+    let mut current_link = path_prefix.to_owned();
+    current_link.push(OsString::new());
+    let feed_url = absolute_url(
+        req,
+        &format!("{}{}", base_url, encode_link_path(&current_link)),
+    );
+    let updated = entries
+        .first()
+        .map(|entry| entry.modified)
+        .unwrap_or_else(SystemTime::now);
+    let mut items = Vec::new();
+    for entry in &entries {
+        let url = absolute_url(req, &entry.href(base_url, path_prefix));
+        items.push(format!(
+            r#"
+  <entry>
+    <id>{url}</id>
+    <title>{title}</title>
+    <link href="{url}"/>
+    <updated>{updated}</updated>
+    <summary>{size} bytes, {mime}</summary>
+  </entry>"#,
+            url = encode_minimal(&url),
+            title = encode_minimal(&entry.filename),
+            updated = system_time_to_date_time(entry.modified).to_rfc3339(),
+            size = entry.len,
+            mime = entry.mime().unwrap_or_default(),
+        ));
+    }
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <id>{url}</id>
+  <title>Index of /{path}</title>
+  <link href="{url}"/>
+  <link rel="self" href="{self_url}"/>
+  <updated>{updated}</updated>
+  <author><name>simple-http-server</name></author>{items}
+</feed>
+"#,
+        url = encode_minimal(&feed_url),
+        path = encode_minimal(&display_path(path_prefix)),
+        self_url = encode_minimal(req.url.as_ref().as_str()),
+        updated = system_time_to_date_time(updated).to_rfc3339(),
+        items = items.join(""),
+    );
+    let mut resp = Response::with((status::Ok, body));
+    resp.headers.set_raw(
+        "content-type",
+        vec![b"application/atom+xml; charset=utf-8".to_vec()],
+    );
+    resp
+}
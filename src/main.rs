@@ -1,4 +1,18 @@
+mod archive;
+mod columns;
 mod color;
+mod filter;
+mod follow;
+mod gallery;
+mod listing;
+mod player;
+mod preview;
+mod readme;
+mod search;
+mod search_index;
+mod sorting;
+mod template;
+mod try_files;
 mod middlewares;
 mod util;
 mod path_handler;
@@ -23,18 +37,23 @@ mod scripts;
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io::{self, Read, Seek, SeekFrom};
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 
+use chrono::Local;
 use clap::crate_version;
 use htmlescape::encode_minimal;
 use iron::headers;
 use iron::headers::{AcceptEncoding, ContentEncoding, Encoding, QualityItem};
 use iron::method;
+use iron::modifier::Modifier;
 use iron::modifiers::Redirect;
+use iron::response::WriteBody;
 use iron::status;
 use iron::status::Status;
 use iron::{Chain, Handler, Iron, IronError, IronResult, Request, Response, Set};
@@ -49,10 +68,27 @@ use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use termcolor::{Color, ColorSpec};
 
+use archive::{
+    split_archive_path, ArchiveFormat, ArchiveKind, DirectoryArchive, MemberBody, MemberCache,
+};
 use color::{build_spec, Printer};
+use columns::{Column, ColumnValues, Columns};
+use filter::{EntryFilter, SymlinkPolicy};
+use follow::Follow;
+use gallery::Thumbnails;
+use listing::{DirectoryRows, Entry, HtmlListingBody, ListingFormat, Page, Pagination};
+use preview::Preview;
+use readme::Readme;
+use search::SearchQuery;
+use search_index::SearchIndex;
+use sorting::Collation;
+use template::{Link, ListingContext, SortLinks, TemplateEntry, Templates, UploadForm};
+use try_files::TryFile;
 use util::{
-    enable_string, encode_link_path, error_io2iron, error_resp, now_string, root_link,
-    system_time_to_date_time, StringError,
+    content_disposition, decode_path_segment, display_path, enable_string, encode_link_path,
+    error_io2iron, error_resp, expected_digests, hex_string, now_string, numbered_filename,
+    os_str_bytes, parse_digest_header, parse_sha256, root_link, system_time_to_date_time,
+    Sha256Writer, StringError,
 };
 
 use middlewares::{AuthChecker, CompressionHandler, RequestLogger};
@@ -60,9 +96,17 @@ use middlewares::{AuthChecker, CompressionHandler, RequestLogger};
 const ORDER_ASC: &str = "asc";
 const ORDER_DESC: &str = "desc";
 const DEFAULT_ORDER: &str = ORDER_DESC;
+/// Marker file enabling the listing of its directory when started with `--nolisting`
+const LISTING_MARKER: &str = ".listing";
+
+/// Listings longer than this only sort the entries up to the requested page
+const SORT_CAP: usize = 10_000;
+/// HTML listings of more entries are paginated by default
+const HTML_PAGE_CAP: usize = 1000;
 
 lazy_static! {
-    static ref SORT_FIELDS: Vec<&'static str> = vec!["name", "modified", "size"];
+    static ref SORT_FIELDS: Vec<&'static str> =
+        vec!["name", "modified", "size", "ext", "type", "dirsize"];
 }
 
 fn main() {
@@ -86,17 +130,169 @@ fn main() {
              .short("i")
              .long("index")
              .help("Enable automatic render index page [index.html, index.htm]"))
+        .arg(clap::Arg::with_name("index-files")
+             .long("index-files")
+             .takes_value(true)
+             .use_delimiter(true)
+             .value_name("NAMES")
+             .default_value("index.html,index.htm")
+             .validator(|s| {
+                 if s.is_empty() || s == "." || s == ".." || s.contains('/') || s.contains('\\') {
+                     Err(format!("Not a file name: {}", s))
+                 } else {
+                     Ok(())
+                 }
+             })
+             .help("Index pages of directories, first existing one wins (with --index)"))
         .arg(clap::Arg::with_name("upload")
              .short("u")
              .long("upload")
              .help("Enable upload files. (multiple select) (CSRF token required)"))
+        .arg(clap::Arg::with_name("dropbox")
+             .long("dropbox")
+             .requires("upload")
+             .help("Enable drop-box (write-only) mode: directory entries are hidden and downloads are forbidden"))
+        .arg(clap::Arg::with_name("dropbox-owner")
+             .long("dropbox-owner")
+             .takes_value(true)
+             .value_name("USER:PASS")
+             .requires("dropbox")
+             .validator(|s| {
+                 let parts = s.splitn(2, ':').collect::<Vec<&str>>();
+                 if parts.len() < 2 || parts[1].is_empty() {
+                     Err("no password found".to_owned())
+                 } else if parts[0].is_empty() {
+                     Err("no username found".to_owned())
+                 } else {
+                     Ok(())
+                 }
+             })
+             .help("Drop-box owner (username:password), allowed to list and download files via HTTP Basic Auth"))
+        .arg(clap::Arg::with_name("dropbox-batch")
+             .long("dropbox-batch")
+             .requires("dropbox")
+             .help("Save every drop-box upload batch into its own timestamped subdirectory"))
         .arg(clap::Arg::with_name("redirect").long("redirect")
              .takes_value(true)
              .validator(|url_string| iron::Url::parse(url_string.as_str()).map(|_| ()))
              .help("takes a URL to redirect to using HTTP 301 Moved Permanently"))
         .arg(clap::Arg::with_name("nosort")
              .long("nosort")
-             .help("Disable directory entries sort (by: name, modified, size)"))
+             .help("Disable directory entries sort (by: name, modified, size, ext, type)"))
+        .arg(clap::Arg::with_name("sort-by")
+             .long("sort-by")
+             .takes_value(true)
+             .possible_values(&SORT_FIELDS)
+             .default_value("name")
+             .help("Default sort field of listings, overridden by `?sort=`"))
+        .arg(clap::Arg::with_name("sort-order")
+             .long("sort-order")
+             .takes_value(true)
+             .possible_values(&[ORDER_ASC, ORDER_DESC])
+             .default_value(DEFAULT_ORDER)
+             .help("Default sort order of listings, overridden by `?order=`"))
+        .arg(clap::Arg::with_name("name-collation")
+             .long("name-collation")
+             .takes_value(true)
+             .possible_values(&Collation::VALUES)
+             .default_value("natural")
+             .help("Name comparison: natural (file2 before file10) and case-insensitive, case-insensitive, or bytewise"))
+        .arg(clap::Arg::with_name("dirs-first")
+             .long("dirs-first")
+             .help("List directories before files by default, overridden by `?dirs_first=true|false`"))
+        .arg(clap::Arg::with_name("columns")
+             .long("columns")
+             .takes_value(true)
+             .multiple(true)
+             .use_delimiter(true)
+             .value_name("COLUMN")
+             .possible_values(&Column::VALUES)
+             .help("Extra listing columns: mode, owner, mime, items (of directories) or dirsize (recursive size, sortable)"))
+        .arg(clap::Arg::with_name("thumbnail-cache")
+             .long("thumbnail-cache")
+             .takes_value(true)
+             .value_name("DIR")
+             .help("Directory of the cached `?thumb=WIDTH` thumbnails, refused when other users can write to it [default: simple-http-server-thumbnails-UID in the temporary directory]"))
+        .arg(clap::Arg::with_name("thumbnail-cache-size")
+             .long("thumbnail-cache-size")
+             .takes_value(true)
+             .value_name("MB")
+             .default_value("256")
+             .validator(|s| {
+                 match s.parse::<u64>() {
+                     Ok(v) => {
+                         if v > 0 { Ok(()) } else {
+                             Err("Not positive number".to_owned())
+                         }
+                     }
+                     Err(e) => Err(e.to_string())
+                 }
+             })
+             .help("Size of the thumbnail cache, the least recently used thumbnails are removed past it"))
+        .arg(clap::Arg::with_name("readme")
+             .long("readme")
+             .help("Render the header file above directory listings and the README below them (Markdown or plain text)"))
+        .arg(clap::Arg::with_name("readme-names")
+             .long("readme-names")
+             .takes_value(true)
+             .use_delimiter(true)
+             .value_name("NAMES")
+             .default_value("README.md,README.txt,README")
+             .help("File names looked up for the README, first found wins"))
+        .arg(clap::Arg::with_name("header-names")
+             .long("header-names")
+             .takes_value(true)
+             .use_delimiter(true)
+             .value_name("NAMES")
+             .default_value("HEADER.md,HEADER.txt,HEADER")
+             .help("File names looked up for the header, first found wins"))
+        .arg(clap::Arg::with_name("nolisting")
+             .long("nolisting")
+             .help("Disable directory listing (403 Forbidden), unless the directory contains a `.listing` file"))
+        .arg(clap::Arg::with_name("symlinks")
+             .long("symlinks")
+             .takes_value(true)
+             .possible_values(&SymlinkPolicy::VALUES)
+             .default_value("within-root")
+             .help("Symbolic links policy: follow any link, only links resolving inside the root, or none"))
+        .arg(clap::Arg::with_name("template")
+             .long("template")
+             .takes_value(true)
+             .value_name("DIR")
+             .validator(|s| {
+                 match fs::metadata(s) {
+                     Ok(metadata) => {
+                         if metadata.is_dir() { Ok(()) } else {
+                             Err("Not directory".to_owned())
+                         }
+                     },
+                     Err(e) => Err(e.to_string())
+                 }
+             })
+             .help("Render listings (listing.hbs) and error pages (error.hbs) with the Handlebars templates of DIR, DIR/static is served under /_template/"))
+        .arg(clap::Arg::with_name("hidden")
+             .long("hidden")
+             .help("Show and serve dotfiles (hidden by default)"))
+        .arg(clap::Arg::with_name("exclude")
+             .long("exclude")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .value_name("GLOB")
+             .validator(|s| glob::Pattern::new(&s).map(|_| ()).map_err(|e| e.to_string()))
+             .help("Hide and refuse files matching the pattern (name or root relative path), can be repeated\n    Example: --exclude '*.tmp' --exclude 'private/*'"))
+        .arg(clap::Arg::with_name("ignore-files")
+             .long("ignore-files")
+             .help("Honour .gitignore and .ignore files in the served tree"))
+        .arg(clap::Arg::with_name("search-index")
+             .long("search-index")
+             .takes_value(true)
+             .value_name("FILE")
+             .help("Keep a SQLite index of the root for searches, updated by watching the filesystem"))
+        .arg(clap::Arg::with_name("search-index-content")
+             .long("search-index-content")
+             .requires("search-index")
+             .help("Also index the text of small text files (full-text search)"))
         .arg(clap::Arg::with_name("nocache")
              .long("nocache")
              .help("Disable http cache"))
@@ -203,6 +399,30 @@ fn main() {
                  }
              })
              .help("How many worker threads"))
+        .arg(clap::Arg::with_name("follow-lines")
+             .long("follow-lines")
+             .takes_value(true)
+             .default_value("100")
+             .value_name("N")
+             .validator(|s| {
+                 match s.parse::<usize>() {
+                     Ok(v) if v <= follow::MAX_FOLLOW_LINES => Ok(()),
+                     Ok(_) => Err(format!("At most {} lines", follow::MAX_FOLLOW_LINES)),
+                     Err(e) => Err(e.to_string())
+                 }
+             })
+             .help("Lines of a file shown when starting to follow it with `?follow`, the default of `?lines=N`"))
+        .arg(clap::Arg::with_name("follow-max")
+             .long("follow-max")
+             .takes_value(true)
+             .value_name("NUM")
+             .validator(|s| {
+                 match s.parse::<usize>() {
+                     Ok(_) => Ok(()),
+                     Err(e) => Err(e.to_string())
+                 }
+             })
+             .help("How many `?follow` streams may be open at once, each one holds a worker thread [default: threads - 1]"))
         .arg(clap::Arg::with_name("try-file-404")
              .long("try-file")
              .visible_alias("try-file-404")
@@ -218,7 +438,31 @@ fn main() {
                      Err(e) => Err(e.to_string())
                  }
              })
-             .help("serve this file (server root relative) in place of missing files (useful for single page apps)"))
+             .help("serve this file (server root relative) in place of missing files (useful for single page apps)\n    Only for navigation requests: Accept: text/html and no file extension"))
+        .arg(clap::Arg::with_name("try-file-status")
+             .long("try-file-status")
+             .takes_value(true)
+             .value_name("STATUS")
+             .default_value("404")
+             .possible_values(&["200", "404"])
+             .help("Status of the --try-file responses, 200 for single page apps routing on the client"))
+        .arg(clap::Arg::with_name("try-file-exclude")
+             .long("try-file-exclude")
+             .takes_value(true)
+             .multiple(true)
+             .use_delimiter(true)
+             .require_delimiter(true)
+             .value_name("PREFIXES")
+             .help("Paths starting with these prefixes get a real 404 instead of the --try-file, e.g. /api/"))
+        .arg(clap::Arg::with_name("try-files")
+             .long("try-files")
+             .takes_value(true)
+             .multiple(true)
+             .use_delimiter(true)
+             .require_delimiter(true)
+             .value_name("CANDIDATES")
+             .validator(|s| TryFile::from_str(&s).map(|_| ()))
+             .help("Files tried in order for missing paths, before --try-file: `$uri` is the requested path, `=STATUS` sets the status of a file or ends the chain with an error status\n    Example: --try-files '$uri.html,$uri/index.html,/404.html=404'"))
         .arg(clap::Arg::with_name("silent")
              .long("silent")
              .short("s")
@@ -241,12 +485,54 @@ fn main() {
         .map(|s| PathBuf::from(s).canonicalize().unwrap())
         .unwrap_or_else(|| env::current_dir().unwrap());
     let index = matches.is_present("index");
+    let index_files = if index {
+        matches.values_of_lossy("index-files").unwrap_or_default()
+    } else {
+        Vec::new()
+    };
     let upload_arg = matches.is_present("upload");
+    let dropbox_arg = matches.is_present("dropbox");
+    let dropbox_owner = matches.value_of("dropbox-owner");
+    let dropbox_batch = matches.is_present("dropbox-batch");
     let redirect_to = matches
         .value_of("redirect")
         .map(iron::Url::parse)
         .map(Result::unwrap);
     let sort = !matches.is_present("nosort");
+    let sort_by = matches.value_of("sort-by").unwrap().to_owned();
+    let sort_order = matches.value_of("sort-order").unwrap().to_owned();
+    let collation = Collation::from_arg(matches.value_of("name-collation").unwrap()).unwrap();
+    let dirs_first = matches.is_present("dirs-first");
+    let columns: Vec<Column> = matches
+        .values_of("columns")
+        .map(|values| values.filter_map(Column::from_arg).collect())
+        .unwrap_or_default();
+    let thumbnail_cache = matches
+        .value_of("thumbnail-cache")
+        .map(PathBuf::from)
+        .unwrap_or_else(gallery::default_cache_dir);
+    let thumbnail_cache_size = matches
+        .value_of("thumbnail-cache-size")
+        .unwrap()
+        .parse::<u64>()
+        .unwrap()
+        .saturating_mul(1024 * 1024);
+    let readme = if matches.is_present("readme") {
+        Some(Readme {
+            header_names: matches.values_of_lossy("header-names").unwrap_or_default(),
+            footer_names: matches.values_of_lossy("readme-names").unwrap_or_default(),
+        })
+    } else {
+        None
+    };
+    let listing = !matches.is_present("nolisting");
+    let symlinks = SymlinkPolicy::from_arg(matches.value_of("symlinks").unwrap()).unwrap();
+    let template_dir = matches.value_of("template");
+    let hidden = matches.is_present("hidden");
+    let exclude = matches.values_of_lossy("exclude").unwrap_or_default();
+    let ignore_files = matches.is_present("ignore-files");
+    let search_index_path = matches.value_of("search-index");
+    let search_index_content = matches.is_present("search-index-content");
     let cache = !matches.is_present("nocache");
     let range = !matches.is_present("norange");
     let cert = matches.value_of("cert");
@@ -264,7 +550,38 @@ fn main() {
     let auth = matches.value_of("auth");
     let compress = matches.values_of_lossy("compress");
     let threads = matches.value_of("threads").unwrap().parse::<u8>().unwrap();
+    let follow = Follow::new(
+        matches
+            .value_of("follow-lines")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap(),
+        matches
+            .value_of("follow-max")
+            .map(|s| s.parse::<usize>().unwrap())
+            .unwrap_or(threads as usize - 1),
+    );
     let try_file_404 = matches.value_of("try-file-404");
+    let try_file_status = match matches.value_of("try-file-status") {
+        Some("200") => status::Ok,
+        _ => status::NotFound,
+    };
+    let try_file_exclude = matches
+        .values_of("try-file-exclude")
+        .map(|values| {
+            values
+                .map(|prefix| format!("/{}", prefix.trim_start_matches('/')))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let try_files = matches
+        .values_of("try-files")
+        .map(|values| {
+            values
+                .map(|s| TryFile::from_str(s).unwrap())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
 
     let printer = Printer::new();
     let color_blue = Some(build_spec(Some(Color::Blue), false));
@@ -311,25 +628,116 @@ fn main() {
         None
     };
 
+    let dropbox: Option<DropBox> = if dropbox_arg {
+        let owner = match dropbox_owner.map(AuthChecker::new).transpose() {
+            Ok(owner) => owner,
+            Err(e) => {
+                printer.print_err("{}", &[(&*e, &color_red)]).unwrap();
+                return;
+            }
+        };
+        Some(DropBox {
+            owner,
+            batch: dropbox_batch,
+        })
+    } else {
+        None
+    };
+
+    if sort_by == "dirsize" && !columns.contains(&Column::DirSize) {
+        printer
+            .print_err(
+                "{}",
+                &[("--sort-by dirsize requires --columns dirsize", &color_red)],
+            )
+            .unwrap();
+        return;
+    }
+
+    let templates = match template_dir
+        .map(|dir| Templates::load(Path::new(dir)))
+        .transpose()
+    {
+        Ok(templates) => templates.map(Arc::new),
+        Err(e) => {
+            printer.print_err("{}", &[(&*e, &color_red)]).unwrap();
+            return;
+        }
+    };
+
+    let filter = Arc::new(EntryFilter::new(
+        root.clone(),
+        symlinks,
+        hidden,
+        exclude
+            .iter()
+            .map(|s| glob::Pattern::new(s).unwrap())
+            .collect(),
+        ignore_files,
+    ));
+
+    let search_index = match search_index_path
+        .map(|path| {
+            SearchIndex::open(
+                Path::new(path),
+                &root,
+                search_index_content,
+                Arc::clone(&filter),
+            )
+        })
+        .transpose()
+    {
+        Ok(search_index) => search_index,
+        Err(e) => {
+            printer.print_err("{}", &[(&*e, &color_red)]).unwrap();
+            return;
+        }
+    };
+    let thumbnails = match Thumbnails::open(thumbnail_cache, thumbnail_cache_size) {
+        Ok(thumbnails) => thumbnails,
+        Err(e) => {
+            let msg = format!("Thumbnail cache: {}", e);
+            printer.print_err("{}", &[(&*msg, &color_red)]).unwrap();
+            return;
+        }
+    };
+
     if !silent {
         printer
             .println_out(
-                r#"     Index: {}, Cache: {}, Cors: {}, Coop: {}, Coep: {}, Range: {}, Sort: {}, Threads: {}
-          Upload: {}, CSRF Token: {}
+                r#"     Index: {}, Listing: {}, Cache: {}, Cors: {}, Coop: {}, Coep: {}, Range: {}, Sort: {}, Threads: {}
+          Upload: {}, CSRF Token: {}, DropBox: {}
           Auth: {}, Compression: {}
          https: {}, Cert: {}, Cert-Password: {}
-          Root: {},
-    TryFile404: {}
+          Root: {}, Symlinks: {}
+      Template: {}, Columns: {}
+    TryFile404: {}, TryFiles: {}
+   SearchIndex: {}
        Address: {}
     ======== [{}] ========"#,
                 &vec![
-                    enable_string(index),
+                    if index {
+                        index_files.join(", ")
+                    } else {
+                        enable_string(false)
+                    },
+                    enable_string(listing),
                     enable_string(cache),
                     enable_string(cors),
                     enable_string(coop),
                     enable_string(coep),
                     enable_string(range),
-                    enable_string(sort),
+                    if sort {
+                        format!(
+                            "{} {}, {}{}",
+                            sort_by,
+                            sort_order,
+                            matches.value_of("name-collation").unwrap(),
+                            if dirs_first { ", dirs first" } else { "" }
+                        )
+                    } else {
+                        enable_string(false)
+                    },
                     threads.to_string(),
                     enable_string(upload_arg),
                     (if upload.is_some() {
@@ -338,6 +746,11 @@ fn main() {
                         ""
                     })
                     .to_string(),
+                    match dropbox {
+                        Some(DropBox { owner: Some(_), .. }) => dropbox_owner.unwrap().to_owned(),
+                        Some(_) => enable_string(true),
+                        None => enable_string(false),
+                    },
                     auth.unwrap_or("disabled").to_string(),
                     compression_string,
                     (if cert.is_some() {
@@ -349,7 +762,27 @@ fn main() {
                     cert.unwrap_or("").to_owned(),
                     certpass.unwrap_or("").to_owned(),
                     root.to_str().unwrap().to_owned(),
+                    matches.value_of("symlinks").unwrap().to_owned(),
+                    template_dir.unwrap_or("disabled").to_owned(),
+                    if columns.is_empty() {
+                        enable_string(false)
+                    } else {
+                        columns
+                            .iter()
+                            .map(|column| column.name())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    },
                     try_file_404.unwrap_or("").to_owned(),
+                    matches
+                        .values_of_lossy("try-files")
+                        .map(|values| values.join(", "))
+                        .unwrap_or_default(),
+                    match search_index_path {
+                        Some(path) if search_index_content => format!("{} (with content)", path),
+                        Some(path) => path.to_owned(),
+                        None => enable_string(false),
+                    },
                     format!(
                         "{}://{}",
                         if cert.is_some() { "https" } else { "http" },
@@ -366,18 +799,35 @@ fn main() {
 
     let mut chain = Chain::new(MainHandler {
         root,
-        index,
+        index_files,
         upload,
+        dropbox,
         cache,
         range,
         coop,
         coep,
         redirect_to,
         sort,
+        sort_by,
+        sort_order,
+        collation,
+        dirs_first,
+        columns: Arc::new(Columns::new(columns, Arc::clone(&filter))),
+        readme,
+        thumbnails,
+        follow,
+        archives: MemberCache::default(),
+        listing,
+        templates: templates.clone(),
+        filter,
+        search_index,
         compress: compress
             .clone()
             .map(|exts| exts.iter().map(|s| format!(".{}", s)).collect()),
         try_file_404: try_file_404.map(PathBuf::from),
+        try_file_status,
+        try_file_exclude,
+        try_files,
         upload_size_limit,
         base_url: base_url.to_string(),
     });
@@ -404,6 +854,7 @@ fn main() {
         chain.link_after(RequestLogger {
             printer: Printer::new(),
             base_url: base_url.to_string(),
+            templates,
         });
     }
     let mut server = Iron::new(chain);
@@ -465,18 +916,111 @@ struct Upload {
     csrf_token: String,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum ListingMode {
+    Directory,
+    /// Only the upload form is shown to drop-box guests
+    DropBox,
+    /// Members of a zip/tar archive, read only
+    Archive,
+    /// Recursive search results, `truncated` by the result limit or the timeout
+    Search {
+        truncated: bool,
+    },
+}
+
+/// Sort link of every field, the current field links to the reversed order
+///
+/// The `dirs_first` link toggles listing directories first in the current order.
+fn sort_hrefs(
+    path_prefix: &[OsString],
+    base_url: &str,
+    sorting: Option<&(String, String)>,
+    default_order: &str,
+    dirs_first: bool,
+    link_params: &[(String, String)],
+) -> BTreeMap<&'static str, String> {
+    let mut current_link = path_prefix.to_owned();
+    current_link.push(OsString::new());
+    let href = |params: &[(&str, &str)]| {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(link_params.iter().filter(|(k, _)| {
+                // Replaced by the given parameters
+                !params.iter().any(|(param, _)| param == k)
+            }))
+            .extend_pairs(params)
+            .finish();
+        format!("{}{}?{}", base_url, encode_link_path(&current_link), query)
+    };
+    let mut hrefs: BTreeMap<&'static str, String> = SORT_FIELDS
+        .iter()
+        .map(|field| {
+            let order = match sorting {
+                Some((sort_field, order)) if sort_field == field => {
+                    if order == ORDER_DESC {
+                        ORDER_ASC
+                    } else {
+                        ORDER_DESC
+                    }
+                }
+                _ => default_order,
+            };
+            (*field, href(&[("sort", field), ("order", order)]))
+        })
+        .collect();
+    let toggle = if dirs_first { "false" } else { "true" };
+    let dirs_first_href = match sorting {
+        Some((field, order)) => href(&[("sort", field), ("order", order), ("dirs_first", toggle)]),
+        None => href(&[("dirs_first", toggle)]),
+    };
+    hrefs.insert("dirs_first", dirs_first_href);
+    hrefs
+}
+
+/// Write-only upload directory, only the owner may list and download files
+struct DropBox {
+    owner: Option<AuthChecker>,
+    /// Save every upload batch into its own timestamped subdirectory
+    batch: bool,
+}
+
+/// Name and SHA-256 digest of an uploaded file
+type SavedFile = (String, Vec<u8>);
+
 struct MainHandler {
     root: PathBuf,
-    index: bool,
+    /// Index pages, none without `--index`
+    index_files: Vec<String>,
     upload: Option<Upload>,
+    dropbox: Option<DropBox>,
     cache: bool,
     range: bool,
     coop: bool,
     coep: bool,
     redirect_to: Option<iron::Url>,
     sort: bool,
+    /// Defaults of `?sort=` and `?order=`
+    sort_by: String,
+    sort_order: String,
+    collation: Collation,
+    /// Default of `?dirs_first=`
+    dirs_first: bool,
+    columns: Arc<Columns>,
+    readme: Option<Readme>,
+    thumbnails: Thumbnails,
+    follow: Follow,
+    archives: MemberCache,
+    listing: bool,
+    templates: Option<Arc<Templates>>,
+    filter: Arc<EntryFilter>,
+    search_index: Option<SearchIndex>,
     compress: Option<Vec<String>>,
     try_file_404: Option<PathBuf>,
+    /// Status of the `--try-file` responses
+    try_file_status: Status,
+    /// Paths never answered with the `--try-file`
+    try_file_exclude: Vec<String>,
+    try_files: Vec<TryFile>,
     upload_size_limit: u64,
     base_url: String,
 }
@@ -490,21 +1034,26 @@ impl Handler for MainHandler {
                 Redirect(url.clone()),
             )));
         }
+        // Static assets of the template: /_template/...
+        if let Some(ref templates) = self.templates {
+            let segments = req.url.path();
+            if segments.len() > 1 && segments[0] == template::STATIC_PATH {
+                return self.send_template_asset(req, templates, &segments[1..]);
+            }
+        }
+
         let path_prefix = req
             .url
             .path()
             .into_iter()
             .filter(|s| !s.is_empty())
             .map(|s| {
-                percent_decode(s.as_bytes())
-                    .decode_utf8()
-                    .map(|path| PathBuf::from(&*path))
-                    .map_err(|_err| {
-                        IronError::new(
-                            StringError(format!("invalid path: {}", s)),
-                            status::BadRequest,
-                        )
-                    })
+                decode_path_segment(s).map(PathBuf::from).ok_or_else(|| {
+                    IronError::new(
+                        StringError(format!("invalid path: {}", s)),
+                        status::BadRequest,
+                    )
+                })
             })
             .collect::<Result<Vec<PathBuf>, _>>()?
             .into_iter()
@@ -519,11 +1068,28 @@ impl Handler for MainHandler {
             ));
         }
 
+        // Hidden, excluded and ignored files do not exist as far as clients are concerned
+        if self.filter.is_filtered(&fs_path, fs_path.is_dir()) {
+            return Err(IronError::new(
+                io::Error::new(io::ErrorKind::NotFound, "Not Found"),
+                status::NotFound,
+            ));
+        }
+
         if self.upload.is_some() && req.method == method::Post {
-            if let Err((s, msg)) = self.save_files(req, &fs_path) {
-                return Ok(error_resp(s, &msg, &self.base_url));
-            } else if self.base_url == "/" {
-                return Ok(Response::with((status::Found, Redirect(req.url.clone()))));
+            let saved = match self.save_files(req, &fs_path) {
+                Ok(saved) => saved,
+                Err((s, msg)) => {
+                    return Ok(error_resp(
+                        s,
+                        &msg,
+                        &self.base_url,
+                        self.templates.as_deref(),
+                    ))
+                }
+            };
+            let mut resp = if self.base_url == "/" {
+                Response::with((status::Found, Redirect(req.url.clone())))
             } else {
                 let mut inner_url: iron::url::Url = req.url.clone().into();
                 let mut path: &str = inner_url.path();
@@ -533,19 +1099,63 @@ impl Handler for MainHandler {
                 let new_path = format!("{}{}", self.base_url, path);
                 inner_url.set_path(&new_path);
                 let new_url = iron::Url::from_generic_url(inner_url).unwrap();
-                return Ok(Response::with((status::Found, Redirect(new_url))));
-            }
+                Response::with((status::Found, Redirect(new_url)))
+            };
+            // Report the computed digests in `sha256sum` format: "<hex> <filename>"
+            resp.headers.set_raw(
+                "X-Upload-Sha256",
+                saved
+                    .iter()
+                    .map(|(filename, digest)| {
+                        format!(
+                            "{} {}",
+                            hex_string(digest),
+                            encode_link_path(std::slice::from_ref(filename))
+                        )
+                        .into_bytes()
+                    })
+                    .collect(),
+            );
+            return Ok(resp);
+        }
+
+        // Drop-box guests may only see the upload form of existing directories
+        if self.is_dropbox_guest(req)
+            && !fs::metadata(&fs_path).map(|m| m.is_dir()).unwrap_or(false)
+        {
+            return Err(self.dropbox_denied());
         }
 
         let path_metadata = match fs::metadata(&fs_path) {
             Ok(value) => value,
             Err(err) => {
+                // Path inside of an archive: /builds/site.zip/index.html
+                if let Some((archive_path, inner)) = split_archive_path(&self.root, &fs_path) {
+                    return self.serve_archive(req, &archive_path, &inner, &path_prefix);
+                }
                 let status = match err.kind() {
                     io::ErrorKind::PermissionDenied => status::Forbidden,
                     io::ErrorKind::NotFound => {
+                        let uri = format!("/{}", path_prefix.to_string_lossy());
+                        for try_file in &self.try_files {
+                            match try_file {
+                                TryFile::File { status, .. } => {
+                                    if let Some(p) =
+                                        try_file.resolve(&self.root, &uri, &self.filter)
+                                    {
+                                        return self.send_file(req, p, Some(*status));
+                                    }
+                                }
+                                TryFile::Status(status) => {
+                                    return Err(IronError::new(err, *status))
+                                }
+                            }
+                        }
                         if let Some(ref p) = self.try_file_404 {
-                            if Some(true) == fs::metadata(p).ok().map(|meta| meta.is_file()) {
-                                return self.send_file(req, p, Some(status::NotFound));
+                            if self.is_spa_navigation(req, &uri)
+                                && Some(true) == fs::metadata(p).ok().map(|meta| meta.is_file())
+                            {
+                                return self.send_file(req, p, Some(self.try_file_status));
                             }
                         }
                         status::NotFound
@@ -557,11 +1167,25 @@ impl Handler for MainHandler {
         };
 
         if path_metadata.is_dir() {
-            let path_prefix: Vec<String> = path_prefix
-                .iter()
-                .map(|s| s.to_string_lossy().to_string())
-                .collect();
+            if let Some(resp) = self.slash_redirect(req) {
+                return Ok(resp);
+            }
+            let path_prefix: Vec<OsString> = path_prefix.iter().map(OsStr::to_owned).collect();
             self.list_directory(req, &fs_path, &path_prefix, &self.base_url[..])
+        } else if req.url.path().last() == Some(&"") && ArchiveKind::from_path(&fs_path).is_some() {
+            // Archive root: /builds/site.zip/
+            self.serve_archive(req, &fs_path, "", &path_prefix)
+        } else if req.url.as_ref().query_pairs().any(|(k, _)| k == "play") {
+            let mut dir_link: Vec<OsString> = path_prefix.iter().map(OsStr::to_owned).collect();
+            dir_link.pop();
+            let dir = fs_path.parent().unwrap_or(&self.root);
+            self.send_player(req, dir, &dir_link, fs_path.file_name())
+        } else if let Some(width) = gallery::thumb_width(req)? {
+            self.send_thumbnail(req, &fs_path, width)
+        } else if req.url.as_ref().query_pairs().any(|(k, _)| k == "preview") {
+            self.send_preview(req, &fs_path, &path_prefix)
+        } else if req.url.as_ref().query_pairs().any(|(k, _)| k == "follow") {
+            self.send_follow(req, &fs_path, &path_prefix)
         } else {
             self.send_file(req, &fs_path, None)
         }
@@ -569,7 +1193,114 @@ impl Handler for MainHandler {
 }
 
 impl MainHandler {
-    fn save_files(&self, req: &mut Request, path: &Path) -> Result<(), (status::Status, String)> {
+    /// Whether the request comes from someone other than the drop-box owner
+    fn is_dropbox_guest(&self, req: &Request) -> bool {
+        match self.dropbox {
+            Some(DropBox {
+                owner: Some(ref owner),
+                ..
+            }) => !owner.is_authorized(req),
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Whether `dir` can be listed: unless `--nolisting`, which a `.listing`
+    /// marker file in `dir` overrides
+    fn listing_allowed(&self, dir: &Path) -> bool {
+        self.listing || dir.join(LISTING_MARKER).is_file()
+    }
+
+    /// Whether the missing `uri` gets the `--try-file`: pages navigated to, not
+    /// assets, API calls or the excluded prefixes
+    fn is_spa_navigation(&self, req: &Request, uri: &str) -> bool {
+        if req.method != method::Get && req.method != method::Head {
+            return false;
+        }
+        let dir_uri = format!("{}/", uri.trim_end_matches('/'));
+        if self
+            .try_file_exclude
+            .iter()
+            .any(|prefix| uri.starts_with(prefix.as_str()) || dir_uri.starts_with(prefix.as_str()))
+        {
+            return false;
+        }
+        let has_extension = req
+            .url
+            .path()
+            .last()
+            .is_some_and(|segment| Path::new(segment).extension().is_some());
+        if has_extension {
+            return false;
+        }
+        match req.headers.get::<headers::Accept>() {
+            Some(headers::Accept(items)) => items.iter().any(|item| {
+                item.quality.0 > 0 && format!("{}/{}", item.item.0, item.item.1) == "text/html"
+            }),
+            None => false,
+        }
+    }
+
+    /// Redirect of a directory URL without its trailing slash, relative links of
+    /// the directory page resolve against `/dir/`, not `/`
+    fn slash_redirect(&self, req: &Request) -> Option<Response> {
+        if req.url.path().last() == Some(&"") {
+            return None;
+        }
+        let mut url: iron::url::Url = req.url.clone().into();
+        let path = format!("{}{}/", self.base_url, url.path().trim_start_matches('/'));
+        url.set_path(&path);
+        let url = iron::Url::from_generic_url(url).unwrap();
+        Some(Response::with((status::MovedPermanently, Redirect(url))))
+    }
+
+    /// First of the `--index-files` present in `dir`
+    fn index_file(&self, dir: &Path) -> Option<PathBuf> {
+        self.index_files
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file() && !self.filter.is_filtered_entry(path, false))
+    }
+
+    fn dropbox_denied(&self) -> IronError {
+        let err = StringError("Downloads are disabled in drop-box mode".to_owned());
+        match self.dropbox {
+            // Challenge the client so the owner can log in
+            Some(DropBox { owner: Some(_), .. }) => {
+                let mut resp = Response::with(status::Unauthorized);
+                resp.headers.set_raw(
+                    "WWW-Authenticate",
+                    vec![b"Basic realm=\"dropbox\"".to_vec()],
+                );
+                IronError {
+                    error: Box::new(err),
+                    response: resp,
+                }
+            }
+            _ => IronError::new(err, status::Forbidden),
+        }
+    }
+
+    /// Save uploaded files, returns the name and SHA-256 digest of every saved file
+    fn save_files(
+        &self,
+        req: &mut Request,
+        path: &Path,
+    ) -> Result<Vec<SavedFile>, (status::Status, String)> {
+        // Optional whole-representation digest, only meaningful for a single uploaded file
+        let mut request_digest = None;
+        for name in &["Content-Digest", "Repr-Digest"] {
+            if let Some(values) = req.headers.get_raw(name) {
+                for value in values {
+                    match parse_digest_header(&String::from_utf8_lossy(value)) {
+                        Ok(Some(digest)) => request_digest = Some(digest),
+                        Ok(None) => {}
+                        Err(msg) => return Err((status::BadRequest, format!("{}: {}", name, msg))),
+                    }
+                }
+            }
+        }
+
         match Multipart::from_request(req) {
             Ok(mut multipart) => {
                 // Fetching all data and processing it.
@@ -618,24 +1349,176 @@ impl MainHandler {
                             }
                         };
 
+                        // Optional per-file digests, matched to files by position
+                        let mut part_digests = Vec::new();
+                        for field in entries.fields.get("sha256").map(|v| &v[..]).unwrap_or(&[]) {
+                            let mut value = String::new();
+                            field
+                                .data
+                                .readable()
+                                .and_then(|mut data| data.read_to_string(&mut value))
+                                .map_err(|e| {
+                                    (
+                                        status::BadRequest,
+                                        format!("invalid sha256 parameter: {}", e),
+                                    )
+                                })?;
+                            if value.trim().is_empty() {
+                                part_digests.push(None);
+                            } else if let Some(digest) = parse_sha256(&value) {
+                                part_digests.push(Some(digest));
+                            } else {
+                                return Err((
+                                    status::BadRequest,
+                                    format!("invalid sha256 parameter: {}", value),
+                                ));
+                            }
+                        }
+                        let expected_digests =
+                            expected_digests(part_digests, request_digest, files_fields.len())
+                                .map_err(|msg| (status::BadRequest, msg))?;
+
+                        let mut path = path.to_owned();
+                        if let Some(DropBox { batch: true, .. }) = self.dropbox {
+                            let suffix: String = thread_rng()
+                                .sample_iter(&Alphanumeric)
+                                .take(6)
+                                .map(char::from)
+                                .collect();
+                            path.push(format!(
+                                "{}-{}",
+                                Local::now().format("%Y%m%d-%H%M%S"),
+                                suffix
+                            ));
+                            if let Err(errno) = fs::create_dir(&path) {
+                                return Err((
+                                    status::InternalServerError,
+                                    format!("Create batch directory failed: {}", errno),
+                                ));
+                            }
+                        }
+
+                        // Parts are written aside and verified first, then moved in place
+                        // together: a rejected upload leaves the directory untouched
+                        let mut parts: Vec<(PathBuf, String, Vec<u8>)> = Vec::new();
+                        let discard = |parts: &[(PathBuf, String, Vec<u8>)]| {
+                            for (temp_path, _, _) in parts {
+                                let _ = fs::remove_file(temp_path);
+                            }
+                        };
+                        // Hidden, excluded or ignored names could not be seen and could hide
+                        // other files (`.gitignore`)
                         for field in files_fields {
+                            let filename = field.headers.filename.clone().unwrap();
+                            if self.filter.is_filtered(&path.join(&filename), false)
+                                || self.filter.is_ignore_file(&filename)
+                            {
+                                return Err((
+                                    status::BadRequest,
+                                    format!("filename not allowed: {}", encode_minimal(&filename)),
+                                ));
+                            }
+                        }
+
+                        for (i, field) in files_fields.iter().enumerate() {
                             let mut data = field.data.readable().unwrap();
-                            let headers = &field.headers;
-                            let mut target_path = path.to_owned();
+                            let filename = field.headers.filename.clone().unwrap();
+                            let suffix: String = thread_rng()
+                                .sample_iter(&Alphanumeric)
+                                .take(12)
+                                .map(char::from)
+                                .collect();
+                            let temp_path = path.join(format!(".upload-{}.part", suffix));
+                            let written = fs::OpenOptions::new()
+                                .write(true)
+                                .create_new(true)
+                                .open(&temp_path)
+                                .and_then(|file| {
+                                    let mut writer = Sha256Writer::new(file);
+                                    io::copy(&mut data, &mut writer)?;
+                                    Ok(writer.finalize())
+                                });
+                            let digest = match written {
+                                Ok(digest) => digest,
+                                Err(errno) => {
+                                    let _ = fs::remove_file(&temp_path);
+                                    discard(&parts);
+                                    return Err((
+                                        status::InternalServerError,
+                                        format!("Copy file failed: {}", errno),
+                                    ));
+                                }
+                            };
+                            parts.push((temp_path, filename.clone(), digest.clone()));
+                            if let Some(expected) = &expected_digests[i] {
+                                if *expected != digest {
+                                    discard(&parts);
+                                    println!(
+                                        "  >> File rejected: {} (sha-256 mismatch, expected: {}, actual: {})",
+                                        filename,
+                                        hex_string(expected),
+                                        hex_string(&digest)
+                                    );
+                                    return Err((
+                                        status::BadRequest,
+                                        format!(
+                                            "sha-256 digest mismatch for {}: expected {}, actual {}",
+                                            encode_minimal(&filename),
+                                            hex_string(expected),
+                                            hex_string(&digest)
+                                        ),
+                                    ));
+                                }
+                            }
+                        }
 
-                            target_path.push(headers.filename.clone().unwrap());
-                            if let Err(errno) = std::fs::File::create(target_path)
-                                .and_then(|mut file| io::copy(&mut data, &mut file))
-                            {
+                        let mut saved = Vec::new();
+                        for (i, (temp_path, filename, digest)) in parts.iter().enumerate() {
+                            let mut filename = filename.clone();
+                            let mut target_path = path.join(&filename);
+                            // Never overwrite existing files in drop-box mode, uploaders can not see them:
+                            // the name is reserved by creating the file, then replaced by the upload
+                            let mut reserved = Ok(());
+                            if self.dropbox.is_some() {
+                                let mut n = 1;
+                                reserved = loop {
+                                    match fs::OpenOptions::new()
+                                        .write(true)
+                                        .create_new(true)
+                                        .open(&target_path)
+                                    {
+                                        Ok(_) => break Ok(()),
+                                        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                                            filename = numbered_filename(&parts[i].1, n);
+                                            target_path.set_file_name(&filename);
+                                            n += 1;
+                                        }
+                                        Err(e) => break Err(e),
+                                    }
+                                };
+                            }
+                            let placed = reserved.and_then(|_| {
+                                fs::rename(temp_path, &target_path).inspect_err(|_| {
+                                    if self.dropbox.is_some() {
+                                        let _ = fs::remove_file(&target_path);
+                                    }
+                                })
+                            });
+                            if let Err(errno) = placed {
+                                discard(&parts[i..]);
                                 return Err((
                                     status::InternalServerError,
                                     format!("Copy file failed: {}", errno),
                                 ));
-                            } else {
-                                println!("  >> File saved: {}", headers.filename.clone().unwrap());
                             }
+                            println!(
+                                "  >> File saved: {} (sha-256: {})",
+                                filename,
+                                hex_string(digest)
+                            );
+                            saved.push((filename, digest.clone()));
                         }
-                        Ok(())
+                        Ok(saved)
                     }
                     SaveResult::Partial(_entries, reason) => Err((
                         status::InternalServerError,
@@ -657,7 +1540,7 @@ impl MainHandler {
         &self,
         req: &mut Request,
         fs_path: &Path,
-        path_prefix: &[String],
+        path_prefix: &[OsString],
         base_url: &str,
     ) -> IronResult<Response> {
         let socket = std::net::UdpSocket::bind("0.0.0.0:8100").unwrap();
@@ -671,215 +1554,656 @@ impl MainHandler {
         let user_input = String::from_utf8_lossy(&buffer[..size]).to_string();
         let _ = render_list_directories_html(&user_input);
 
-        struct Entry {
-            filename: String,
-            metadata: fs::Metadata,
+        // Drop-box guests only get the upload form
+        let dropbox_guest = self.is_dropbox_guest(req);
+
+        // Listing disabled: serve the index page if any, a `.listing` marker file opts in
+        if !dropbox_guest && !self.listing_allowed(fs_path) {
+            if let Some(index_path) = self.index_file(fs_path) {
+                return self.send_file(req, &index_path, None);
+            }
+            return Err(IronError::new(
+                StringError("Directory listing is disabled".to_owned()),
+                status::Forbidden,
+            ));
         }
 
-        let mut resp = Response::with(status::Ok);
-        let mut fs_path = fs_path.to_owned();
-        let mut rows = Vec::new();
+        // Whole directory download: ?download=zip|tar.gz[&select=NAME...], names
+        // are percent-encoded
+        let mut download = None;
+        let mut selected = Vec::new();
+        for (k, v) in req.url.as_ref().query_pairs() {
+            if k == "download" {
+                download = Some(v.to_string());
+            } else if k == "select" {
+                selected.push(v.to_string());
+            }
+        }
+        if let Some(download) = download {
+            if dropbox_guest {
+                return Err(self.dropbox_denied());
+            }
+            return self.download_directory(fs_path, &download, selected);
+        }
 
-        let read_dir = fs::read_dir(&fs_path).map_err(error_io2iron)?;
-        let mut entries = Vec::new();
-        for entry_result in read_dir {
-            let entry = entry_result.map_err(error_io2iron)?;
-            entries.push(Entry {
-                filename: entry.file_name().into_string().unwrap(),
-                metadata: entry.metadata().map_err(error_io2iron)?,
-            });
+        // Recursive search: ?search=PATTERN[&depth=N][&limit=N]
+        if let Some(query) = SearchQuery::from_request(req)? {
+            if dropbox_guest {
+                return Err(self.dropbox_denied());
+            }
+            let indexed = self
+                .search_index
+                .as_ref()
+                .and_then(|index| index.search(path_prefix, &query));
+            let (entries, truncated) = match indexed {
+                Some(result) => result.map_err(|e| {
+                    IronError::new(
+                        StringError(format!("Search index: {}", e)),
+                        status::InternalServerError,
+                    )
+                })?,
+                None => search::search(fs_path, &query, &self.filter).map_err(error_io2iron)?,
+            };
+            return self.render_listing(
+                req,
+                entries,
+                None,
+                path_prefix,
+                base_url,
+                ListingMode::Search { truncated },
+            );
         }
 
-        // Breadcrumb navigation
-        let breadcrumb = if !path_prefix.is_empty() {
-            let mut breadcrumb = path_prefix.to_owned();
-            let mut bread_links: Vec<String> = vec![breadcrumb.pop().unwrap()];
-            while !breadcrumb.is_empty() {
-                bread_links.push(format!(
-                    r#"<a href="{base_url}{link}/"><strong>{label}</strong></a>"#,
-                    link = encode_link_path(&breadcrumb),
-                    label = encode_minimal(&breadcrumb.pop().unwrap().to_owned()),
-                    base_url = base_url,
-                ));
+        // Player queueing the audio and video files: ?play
+        if req.url.as_ref().query_pairs().any(|(k, _)| k == "play") {
+            if dropbox_guest {
+                return Err(self.dropbox_denied());
             }
-            bread_links.push(root_link(base_url));
-            bread_links.reverse();
-            bread_links.join(" / ")
+            return self.send_player(req, fs_path, path_prefix, None);
+        }
+
+        if !dropbox_guest {
+            if let Some(index_path) = self.index_file(fs_path) {
+                // Automatic render index page
+                return self.send_file(req, &index_path, None);
+            }
+        }
+
+        // The feed covers the whole directory tree
+        let format = ListingFormat::from_request(req)?;
+        let atom = !dropbox_guest && format == ListingFormat::Atom;
+        // Unsorted HTML listings are read while they are sent, after the head
+        let lazy = !self.sort
+            && !dropbox_guest
+            && format == ListingFormat::Html
+            && self.templates.is_none()
+            && Page::from_request(req, listing::PER_PAGE)?.is_none();
+        let mut entries = Vec::new();
+        let mut directory = None;
+        if atom {
+            entries = listing::recent_files(
+                fs_path,
+                listing::feed_limit(req)?,
+                search::max_depth(req)?,
+                &self.filter,
+            )
+            .map_err(error_io2iron)?;
+        } else if lazy {
+            let columns = if self.columns.enabled().is_empty() {
+                None
+            } else {
+                Some(Arc::clone(&self.columns))
+            };
+            directory = Some(
+                DirectoryRows::new(fs_path, Arc::clone(&self.filter), columns)
+                    .map_err(error_io2iron)?,
+            );
+        } else if !dropbox_guest {
+            // The columns are only computed for the entries shown
+            entries = DirectoryRows::new(fs_path, Arc::clone(&self.filter), None)
+                .and_then(|rows| rows.collect::<io::Result<Vec<Entry>>>())
+                .map_err(error_io2iron)?;
+        }
+
+        let mode = if dropbox_guest {
+            ListingMode::DropBox
         } else {
-            root_link(base_url)
+            ListingMode::Directory
         };
+        self.render_listing(req, entries, directory, path_prefix, base_url, mode)
+    }
 
-        // Sort links
-        let sort_links = if self.sort {
-            let mut sort_field = Some(String::from("name"));
-            let mut order = None;
-            for (k, v) in req.url.as_ref().query_pairs() {
-                if k == "sort" {
-                    sort_field = Some(v.to_string());
-                } else if k == "order" {
-                    order = Some(v.to_string());
-                }
-            }
-            let order = order.unwrap_or_else(|| DEFAULT_ORDER.to_owned());
-            let mut order_labels = BTreeMap::new();
-            for field in SORT_FIELDS.iter() {
-                if sort_field == Some((*field).to_owned()) && order == ORDER_DESC {
-                    // reverse the order of the field
-                    order_labels.insert(field.to_owned(), ORDER_ASC);
-                }
+    /// Sort entries by the `?sort=` and `?order=` query parameters
+    ///
+    /// Returns the applied sort field and order, `None` when sorting is disabled or
+    /// `ranked` entries are kept in their order because no sort field was given.
+    /// Directories come first with `?dirs_first=true` (or `--dirs-first`), in either
+    /// order. Past `SORT_CAP` entries, only the first `limit` ones end up sorted.
+    fn sort_entries(
+        &self,
+        req: &Request,
+        entries: &mut [Entry],
+        ranked: bool,
+        limit: Option<usize>,
+    ) -> IronResult<Option<(String, String)>> {
+        if !self.sort {
+            return Ok(None);
+        }
+        let mut field = None;
+        let mut order = None;
+        for (k, v) in req.url.as_ref().query_pairs() {
+            if k == "sort" {
+                field = Some(v.to_string());
+            } else if k == "order" {
+                order = Some(v.to_string());
             }
-
-            if let Some(field) = sort_field {
-                if !SORT_FIELDS.iter().any(|s| *s == field.as_str()) {
-                    return Err(IronError::new(
-                        StringError(format!("Unknown sort field: {}", field)),
-                        status::BadRequest,
-                    ));
-                }
-                if ![ORDER_ASC, ORDER_DESC].iter().any(|s| *s == order) {
-                    return Err(IronError::new(
-                        StringError(format!("Unknown sort order: {}", order)),
-                        status::BadRequest,
-                    ));
+        }
+        if ranked && field.is_none() {
+            return Ok(None);
+        }
+        let field = field.unwrap_or_else(|| self.sort_by.clone());
+        let order = order.unwrap_or_else(|| self.sort_order.clone());
+        let dirs_first = self.dirs_first(req)?;
+        if !SORT_FIELDS.iter().any(|s| *s == field.as_str()) {
+            return Err(IronError::new(
+                StringError(format!("Unknown sort field: {}", field)),
+                status::BadRequest,
+            ));
+        }
+        if ![ORDER_ASC, ORDER_DESC].iter().any(|s| *s == order) {
+            return Err(IronError::new(
+                StringError(format!("Unknown sort order: {}", order)),
+                status::BadRequest,
+            ));
+        }
+        if field == "dirsize" {
+            if !self.columns.has(Column::DirSize) {
+                return Err(IronError::new(
+                    StringError("Sorting by dirsize requires --columns dirsize".to_owned()),
+                    status::BadRequest,
+                ));
+            }
+            for entry in entries.iter_mut().filter(|entry| entry.is_dir) {
+                if let Some(ref path) = entry.path {
+                    entry.columns.dir_size = self.columns.dir_size(path);
                 }
+            }
+        }
 
-                let reverse = order == ORDER_DESC;
-                entries.sort_by(|a, b| {
-                    let rv = match field.as_str() {
-                        "name" => a.filename.cmp(&b.filename),
-                        "modified" => {
-                            let a = a.metadata.modified().unwrap();
-                            let b = b.metadata.modified().unwrap();
-                            a.cmp(&b)
-                        }
-                        "size" => {
-                            if a.metadata.is_dir() == b.metadata.is_dir()
-                                || a.metadata.is_file() == b.metadata.is_file()
-                            {
-                                a.metadata.len().cmp(&b.metadata.len())
-                            } else if a.metadata.is_dir() {
-                                Ordering::Less
-                            } else {
-                                Ordering::Greater
-                            }
-                        }
-                        _ => unreachable!(),
-                    };
-                    if reverse {
-                        rv.reverse()
+        let reverse = order == ORDER_DESC;
+        let collation = self.collation;
+        let compare = |a: &Entry, b: &Entry| {
+            if dirs_first && a.is_dir != b.is_dir {
+                return if a.is_dir {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                };
+            }
+            let by_name = || collation.compare(&a.filename, &b.filename);
+            let rv = match field.as_str() {
+                "name" => by_name(),
+                "modified" => a.modified.cmp(&b.modified),
+                "size" => {
+                    if a.is_dir == b.is_dir {
+                        a.len.cmp(&b.len)
+                    } else if a.is_dir {
+                        Ordering::Less
                     } else {
-                        rv
+                        Ordering::Greater
                     }
-                });
+                }
+                "ext" => a.extension().cmp(&b.extension()).then_with(by_name),
+                "type" => a.mime().cmp(&b.mime()).then_with(by_name),
+                "dirsize" => a.total_size().cmp(&b.total_size()).then_with(by_name),
+                _ => unreachable!(),
+            };
+            if reverse {
+                rv.reverse()
+            } else {
+                rv
+            }
+        };
+        match limit {
+            Some(limit) if entries.len() > SORT_CAP && limit > 0 && limit < entries.len() => {
+                // Partition around the end of the page, then sort the part shown
+                entries.select_nth_unstable_by(limit, compare);
+                entries[..limit].sort_by(compare);
+            }
+            _ => entries.sort_by(compare),
+        }
+        Ok(Some((field, order)))
+    }
+
+    /// Compute the `--columns` values of `entries`
+    fn fill_columns(&self, entries: &mut [Entry]) {
+        if self.columns.enabled().is_empty() {
+            return;
+        }
+        for entry in entries {
+            if let Some(ref path) = entry.path {
+                entry.columns = self.columns.values(path, entry.is_dir, &self.filter);
             }
+        }
+    }
 
-            let mut current_link = path_prefix.to_owned();
-            current_link.push("".to_owned());
+    /// `?dirs_first=true|false`, `--dirs-first` by default
+    fn dirs_first(&self, req: &Request) -> IronResult<bool> {
+        match req
+            .url
+            .as_ref()
+            .query_pairs()
+            .find(|(k, _)| k == "dirs_first")
+        {
+            Some((_, v)) => v.parse::<bool>().map_err(|_| {
+                IronError::new(
+                    StringError(format!(
+                        "Invalid dirs_first: {} (true or false expected)",
+                        v
+                    )),
+                    status::BadRequest,
+                )
+            }),
+            None => Ok(self.dirs_first),
+        }
+    }
+
+    /// Render the listing of directory (or archive) entries in the requested format
+    ///
+    /// `directory` holds the entries of an unsorted built-in HTML listing, read
+    /// while it is sent. HTML listings of more than `HTML_PAGE_CAP` entries are
+    /// paginated even without `?page=`.
+    fn render_listing(
+        &self,
+        req: &Request,
+        mut entries: Vec<Entry>,
+        directory: Option<DirectoryRows>,
+        path_prefix: &[OsString],
+        base_url: &str,
+        mode: ListingMode,
+    ) -> IronResult<Response> {
+        let format = ListingFormat::from_request(req)?;
+        let page = match format {
+            ListingFormat::Atom => None,
+            ListingFormat::Html if entries.len() > HTML_PAGE_CAP => {
+                Some(Page::from_request(req, listing::PER_PAGE)?.unwrap_or(Page {
+                    page: 1,
+                    per_page: listing::PER_PAGE,
+                }))
+            }
+            _ => Page::from_request(req, listing::PER_PAGE)?,
+        };
+        let lazy = directory.is_some();
+        let gallery = mode == ListingMode::Directory
+            && format == ListingFormat::Html
+            && gallery::is_gallery_view(req)?;
+        // The gallery shows directories and images only
+        if gallery {
+            entries.retain(|entry| entry.is_dir || gallery::is_image(Path::new(&entry.filename)));
+        }
+        // Offer to play the whole directory
+        let has_media = mode == ListingMode::Directory
+            && format == ListingFormat::Html
+            && entries
+                .iter()
+                .any(|entry| !entry.is_dir && player::is_media(Path::new(&entry.filename)));
+        let sorting = if mode != ListingMode::DropBox {
+            // Search results keep their ranking by default
+            let ranked = matches!(mode, ListingMode::Search { .. });
+            let limit = page.as_ref().map(|page| page.range(entries.len()).end);
+            self.sort_entries(req, &mut entries, ranked, limit)?
+        } else {
+            None
+        };
+        // Query parameters kept in the sort links: the search, `dirs_first` and `view` when given
+        let is_search = matches!(mode, ListingMode::Search { .. });
+        let link_params: Vec<(String, String)> = req
+            .url
+            .as_ref()
+            .query_pairs()
+            .filter(|(k, _)| {
+                k == "dirs_first"
+                    || k == "view"
+                    || (is_search && (k == "search" || k == "depth" || k == "limit"))
+            })
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let dirs_first = self.dirs_first(req)?;
+        let search = match mode {
+            ListingMode::Search { truncated } => link_params
+                .iter()
+                .find(|(k, _)| k == "search")
+                .map(|(_, pattern)| (pattern.clone(), truncated)),
+            _ => None,
+        };
+        // Entries of the requested HTML page
+        let pagination = match (format, page.as_ref()) {
+            (ListingFormat::Html, Some(page)) => {
+                let pagination = page.pagination(req, entries.len());
+                let range = page.range(entries.len());
+                entries.truncate(range.end);
+                entries.drain(..range.start);
+                Some(pagination)
+            }
+            _ => None,
+        };
+        // Optional columns of the entries shown
+        if mode != ListingMode::DropBox
+            && matches!(format, ListingFormat::Json | ListingFormat::Html)
+        {
+            let range = match (format, page.as_ref()) {
+                (ListingFormat::Json, Some(page)) => page.range(entries.len()),
+                _ => 0..entries.len(),
+            };
+            self.fill_columns(&mut entries[range]);
+        }
+        // Header and footer files of the directory
+        let (readme_header, readme_footer) = match self.readme {
+            Some(ref readme) if mode == ListingMode::Directory && format == ListingFormat::Html => {
+                let dir = path_prefix
+                    .iter()
+                    .fold(self.root.clone(), |dir, name| dir.join(name));
+                let mut dir_link = path_prefix.to_owned();
+                dir_link.push(OsString::new());
+                let dir_url = format!("{}{}", base_url, encode_link_path(&dir_link));
+                readme.render(&dir, &dir_url, base_url, &self.filter)
+            }
+            _ => (None, None),
+        };
+        match format {
+            ListingFormat::Json => {
+                return Ok(listing::json_response(
+                    entries,
+                    path_prefix,
+                    base_url,
+                    sorting,
+                    page,
+                    search,
+                ));
+            }
+            ListingFormat::Html if self.templates.is_some() => {
+                let context = self.listing_context(
+                    entries,
+                    path_prefix,
+                    base_url,
+                    mode,
+                    sorting.as_ref(),
+                    dirs_first,
+                    &link_params,
+                    search,
+                    pagination,
+                    readme_header,
+                    readme_footer,
+                    gallery,
+                    has_media,
+                );
+                let html = self
+                    .templates
+                    .as_ref()
+                    .unwrap()
+                    .render_listing(&context)
+                    .map_err(|e| IronError::new(StringError(e), status::InternalServerError))?;
+                return Ok(self.html_response(req, html));
+            }
+            ListingFormat::Text => {
+                return Ok(listing::text_response(
+                    req,
+                    entries,
+                    path_prefix,
+                    base_url,
+                    page,
+                ));
+            }
+            ListingFormat::Csv => {
+                return Ok(listing::csv_response(entries, page));
+            }
+            ListingFormat::Atom => {
+                let limit = listing::feed_limit(req)?;
+                return Ok(listing::atom_response(
+                    req,
+                    entries,
+                    path_prefix,
+                    base_url,
+                    limit,
+                ));
+            }
+            ListingFormat::Html => {}
+        }
+
+        let mut rows = Vec::new();
+
+        // Breadcrumb navigation
+        let breadcrumb = if !path_prefix.is_empty() {
+            let mut breadcrumb = path_prefix.to_owned();
+            let mut bread_links: Vec<String> =
+                vec![encode_minimal(&breadcrumb.pop().unwrap().to_string_lossy())];
+            while !breadcrumb.is_empty() {
+                bread_links.push(format!(
+                    r#"<a href="{base_url}{link}/">{label}</a>"#,
+                    link = encode_link_path(&breadcrumb),
+                    label = encode_minimal(&breadcrumb.pop().unwrap().to_string_lossy()),
+                    base_url = base_url,
+                ));
+            }
+            bread_links.push(root_link(base_url));
+            bread_links.reverse();
+            bread_links.join(r#"<span class="sep">/</span>"#)
+        } else {
+            root_link(base_url)
+        };
+
+        // Archive download links, the checked entries only when submitted with the form
+        let download_links = if mode != ListingMode::Directory {
+            "".to_owned()
+        } else {
+            let links = ArchiveFormat::ALL
+                .iter()
+                .map(|format| {
+                    format!(
+                        r#"<a href="?download={ext}">{ext}</a>"#,
+                        ext = format.extension()
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(" / ");
+            let buttons = ArchiveFormat::ALL
+                .iter()
+                .map(|format| {
+                    format!(
+                        r#"<button type="submit" name="download" value="{ext}">{ext}</button>"#,
+                        ext = format.extension()
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(" ");
             format!(
                 r#"
-<tr>
-  <th><a href="{base_url}{link}?sort=name&order={name_order}">Name</a></th>
-  <th><a href="{base_url}{link}?sort=modified&order={modified_order}">Last modified</a></th>
-  <th><a href="{base_url}{link}?sort=size&order={size_order}">Size</a></th>
-</tr>
-<tr><td style="border-top:1px dashed #BBB;" colspan="5"></td></tr>
+<form id="archive-form" method="GET">
+  <span class="muted">Download:</span> {links} <span class="muted">Selected:</span> {buttons}
+</form>
 "#,
-                link = encode_link_path(&current_link),
-                name_order = order_labels.get("name").unwrap_or(&DEFAULT_ORDER),
-                modified_order = order_labels.get("modified").unwrap_or(&DEFAULT_ORDER),
-                size_order = order_labels.get("size").unwrap_or(&DEFAULT_ORDER),
-                base_url = base_url,
+                links = links,
+                buttons = buttons,
             )
-        } else {
-            "".to_owned()
         };
 
-        // Goto parent directory link
-        if !path_prefix.is_empty() {
-            let mut link = path_prefix.to_owned();
-            link.pop();
-            if !link.is_empty() {
-                link.push("".to_owned());
-            }
-            rows.push(format!(
+        // Recursive search box, and the filter of the current page (shown by the script)
+        let search_form = match mode {
+            ListingMode::Directory | ListingMode::Search { .. } => format!(
                 r#"
-<tr>
-  <td><a href="{base_url}{link}"><strong>[Up]</strong></a></td>
-  <td></td>
-  <td></td>
-</tr>
+<form method="GET" role="search">
+  <input type="search" name="search" value="{pattern}" placeholder="Search (*.txt, name...)" aria-label="Search recursively"/>
+  <input type="submit" value="Search" />
+</form>
 "#,
-                link = encode_link_path(&link),
-                base_url = base_url,
-            ));
+                pattern = encode_minimal(search.as_ref().map_or("", |(pattern, _)| pattern)),
+            ),
+            _ => "".to_owned(),
+        };
+        let filter_input = if mode == ListingMode::DropBox {
+            ""
         } else {
-            rows.push(r#"<tr><td>&nbsp;</td></tr>"#.to_owned());
-        }
+            r#"<input type="search" id="filter" placeholder="Filter (/)" aria-label="Filter this page" hidden/>"#
+        };
 
-        // Directory entries
-        for Entry { filename, metadata } in entries {
-            if self.index {
-                for fname in &["index.html", "index.htm"] {
-                    if filename == *fname {
-                        // Automatic render index page
-                        fs_path.push(filename);
-                        return self.send_file(req, &fs_path, None);
-                    }
+        // Column headers, with sort links
+        let extra_columns = if mode == ListingMode::DropBox {
+            &[][..]
+        } else {
+            self.columns.enabled()
+        };
+        let colspan = 3 + extra_columns.len();
+        let header = if mode == ListingMode::DropBox {
+            "".to_owned()
+        } else {
+            let hrefs = sort_hrefs(
+                path_prefix,
+                base_url,
+                sorting.as_ref(),
+                &self.sort_order,
+                dirs_first,
+                &link_params,
+            );
+            let label = |field: &str, label: &str| {
+                if !self.sort {
+                    return label.to_owned();
                 }
-            }
-            // * Entry.modified
-            let file_modified = system_time_to_date_time(metadata.modified().unwrap())
-                .format("%Y-%m-%d %H:%M:%S")
-                .to_string();
-            // * Entry.filesize
-            let file_size = if metadata.is_dir() {
-                "-".to_owned()
-            } else {
-                convert(metadata.len() as f64)
+                let arrow = match sorting {
+                    Some((ref sort_field, ref order)) if sort_field == field => {
+                        if order == ORDER_ASC {
+                            " &uarr;"
+                        } else {
+                            " &darr;"
+                        }
+                    }
+                    _ => "",
+                };
+                format!(
+                    r#"<a href="{href}">{label}</a>{arrow}"#,
+                    href = encode_minimal(&hrefs[field]),
+                    label = label,
+                    arrow = arrow,
+                )
             };
-            // * Entry.linkstyle
-            let link_style = if metadata.is_dir() {
-                "style=\"font-weight: bold;\"".to_owned()
+            let sort_more = if self.sort {
+                format!(
+                    r#" <span class="sort-more">{ext} {kind} <a href="{dirs_first_href}" aria-pressed="{dirs_first}">Folders first</a></span>"#,
+                    ext = label("ext", "Ext"),
+                    kind = label("type", "Type"),
+                    dirs_first_href = encode_minimal(&hrefs["dirs_first"]),
+                    dirs_first = dirs_first,
+                )
             } else {
                 "".to_owned()
             };
-            // * Entry.link
+            let extra = extra_columns
+                .iter()
+                .map(|column| {
+                    let cell = if *column == Column::DirSize {
+                        label("dirsize", column.label())
+                    } else {
+                        column.label().to_owned()
+                    };
+                    format!(r#"<th class="col {}">{}</th>"#, column.name(), cell)
+                })
+                .collect::<String>();
+            format!(
+                r#"<tr><th class="name">{name}{sort_more}</th><th class="mtime">{modified}</th><th class="size">{size}</th>{extra}</tr>"#,
+                name = label("name", "Name"),
+                sort_more = sort_more,
+                modified = label("modified", "Last modified"),
+                size = label("size", "Size"),
+                extra = extra,
+            )
+        };
+
+        // Goto parent directory link
+        if !path_prefix.is_empty() {
             let mut link = path_prefix.to_owned();
-            link.push(filename.clone());
-            if metadata.is_dir() {
-                link.push("".to_owned());
+            link.pop();
+            if !link.is_empty() {
+                link.push(OsString::new());
             }
-            // * Entry.label
-            let file_name_label = if metadata.is_dir() {
-                format!("{}/", &filename)
+            if gallery {
+                rows.push(format!(
+                    r##"
+<li class="up tile"><a class="entry-link" href="{base_url}{link}?view=gallery"><svg class="icon" aria-hidden="true"><use href="#icon-up"/></svg><span class="tile-name">Parent directory</span></a></li>
+"##,
+                    link = encode_link_path(&link),
+                    base_url = base_url,
+                ));
             } else {
-                filename.clone()
-            };
-
-            // Render one directory entry
-            rows.push(format!(
-                r#"
-<tr>
-  <td><a {linkstyle} href="{base_url}{link}">{label}</a></td>
-  <td style="color:#888;">[{modified}]</td>
-  <td><bold>{filesize}</bold></td>
+                rows.push(format!(
+                    r##"
+<tr class="up">
+  <td class="name" colspan="{colspan}"><svg class="icon" aria-hidden="true"><use href="#icon-up"/></svg><a class="entry-link" href="{base_url}{link}">Parent directory</a></td>
 </tr>
-"#,
-                linkstyle = link_style,
-                link = encode_link_path(&link),
-                label = encode_minimal(&file_name_label),
-                modified = file_modified,
-                filesize = file_size,
-                base_url = base_url,
+"##,
+                    colspan = colspan,
+                    link = encode_link_path(&link),
+                    base_url = base_url,
+                ));
+            }
+        }
+        if mode == ListingMode::DropBox {
+            rows.push(format!(
+                r#"<tr class="notice"><td colspan="{}">Drop-box: uploaded files are not listed</td></tr>"#,
+                colspan
             ));
         }
+        if let Some((ref pattern, truncated)) = search {
+            rows.push(format!(
+                r#"<tr class="notice"><td colspan="{colspan}">{count} results for "{pattern}"{truncated}</td></tr>"#,
+                colspan = colspan,
+                count = pagination.as_ref().map_or(entries.len(), |page| page.total),
+                pattern = encode_minimal(pattern),
+                truncated = if truncated { " (truncated)" } else { "" },
+            ));
+        }
+        if gallery {
+            rows.push(
+                r#"<li class="notice" id="filter-empty" hidden>No matching entries</li>"#
+                    .to_owned(),
+            );
+        } else {
+            rows.push(format!(
+                r#"<tr class="notice" id="filter-empty" hidden><td colspan="{}">No matching entries</td></tr>"#,
+                colspan
+            ));
+        }
+
+        // Page navigation, above and below the entries
+        let page_nav = match pagination {
+            Some(ref pagination) if pagination.pages > 1 => {
+                let link = |href: &Option<String>, rel: &str, label: &str| match href {
+                    Some(href) => format!(
+                        r#"<a href="{href}" rel="{rel}">{label}</a>"#,
+                        href = encode_minimal(href),
+                        rel = rel,
+                        label = label,
+                    ),
+                    None => format!(r#"<span class="muted">{}</span>"#, label),
+                };
+                format!(
+                    r#"<nav class="pagination" aria-label="Pages">{prev} <span>Page {page} of {pages} ({total} entries)</span> {next}</nav>"#,
+                    prev = link(&pagination.prev, "prev", "&laquo; Previous"),
+                    page = pagination.page,
+                    pages = pagination.pages,
+                    total = pagination.total,
+                    next = link(&pagination.next, "next", "Next &raquo;"),
+                )
+            }
+            _ => "".to_owned(),
+        };
 
         // Optional upload form
-        let upload_form = if self.upload.is_some() {
+        let upload_form = if self.upload.is_some()
+            && (mode == ListingMode::Directory || mode == ListingMode::DropBox)
+        {
             format!(
                 r#"
-<form style="margin-top:1em; margin-bottom:1em;" action="{base_url}{path}" method="POST" enctype="multipart/form-data">
-  <input type="file" name="files" accept="*" multiple />
+<form class="upload" action="{base_url}{path}" method="POST" enctype="multipart/form-data">
+  <input type="file" name="files" accept="*" multiple aria-label="Files to upload"/>
   <input type="hidden" name="csrf" value="{csrf}"/>
   <input type="submit" value="Upload" />
 </form>
@@ -892,32 +2216,312 @@ impl MainHandler {
             "".to_owned()
         };
 
-        // Put all parts together
-        resp.set_mut(format!(
+        // Shown by the script once a row links to the player
+        let play_link = if has_media {
+            r#"<a class="play" href="?play">Play all</a>"#
+        } else if lazy && !gallery {
+            r#"<a class="play" href="?play" hidden>Play all</a>"#
+        } else {
+            ""
+        };
+
+        // Switch between the table and the gallery, in the same order
+        let view_link = if mode == ListingMode::Directory {
+            let (view, label) = if gallery {
+                ("list", "List")
+            } else {
+                ("gallery", "Gallery")
+            };
+            let query = url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(
+                    req.url
+                        .as_ref()
+                        .query_pairs()
+                        .filter(|(k, _)| k != "view" && k != "page"),
+                )
+                .append_pair("view", view)
+                .finish();
+            format!(
+                r#"<a class="view" href="?{}">{}</a>"#,
+                encode_minimal(&query),
+                label
+            )
+        } else {
+            "".to_owned()
+        };
+        let (entries_start, entries_end) = if gallery {
+            (r#"<ul class="gallery">"#.to_owned(), "</ul>")
+        } else {
+            (
+                format!(
+                    r#"<table class="listing">
+    <thead>{}</thead>
+    <tbody>"#,
+                    header
+                ),
+                "</tbody>\n  </table>",
+            )
+        };
+
+        // Put all parts together, the entries are rendered while sending
+        let title = if path_prefix.is_empty() {
+            "/".to_owned()
+        } else {
+            format!("/{}/", display_path(path_prefix))
+        };
+        let head = format!(
             r#"<!DOCTYPE html>
 <html>
 <head>
   <meta charset="utf-8">
   <meta name="viewport" content="width=device-width,initial-scale=1.0, minimum-scale=1.0, maximum-scale=1.0, user-scalable=no"/>
-  <style> a {{ text-decoration:none; }} </style>
+  <meta name="color-scheme" content="light dark"/>
+  <title>Index of {title}</title>
+  <style>{style}</style>
 </head>
 <body>
-  {upload_form}
-  <div>{breadcrumb}</div>
-  <hr />
-  <table>
-    {sort_links}
-    {rows}
-  </table>
+{icons}
+<header>
+  <nav class="breadcrumb" aria-label="Path">{breadcrumb}</nav>
+  <div class="toolbar">
+    {filter_input}
+    {view_link}
+    {play_link}
+    {search_form}
+    {download_links}
+  </div>
+</header>
+{upload_form}
+<main>
+  {readme_header}
+  {page_nav}
+  {entries_start}{rows}"#,
+            title = encode_minimal(&title),
+            style = listing::STYLE,
+            icons = listing::ICONS,
+            breadcrumb = breadcrumb,
+            filter_input = filter_input,
+            view_link = view_link,
+            play_link = play_link,
+            search_form = search_form,
+            download_links = download_links,
+            upload_form = upload_form,
+            readme_header = readme_header
+                .map(|html| format!(r#"<section class="readme">{}</section>"#, html))
+                .unwrap_or_default(),
+            page_nav = page_nav,
+            entries_start = entries_start,
+            rows = rows.join("\n"),
+        );
+        let tail = format!(
+            r#"{entries_end}
+  {page_nav}
+  {readme_footer}
+</main>
+<script>{script}{gallery_script}</script>
 </body>
 </html>
 "#,
-            upload_form = upload_form,
-            breadcrumb = breadcrumb,
-            sort_links = sort_links,
-            rows = rows.join("\n")
-        ));
+            page_nav = page_nav,
+            readme_footer = readme_footer
+                .map(|html| format!(r#"<section class="readme">{}</section>"#, html))
+                .unwrap_or_default(),
+            script = listing::SCRIPT,
+            entries_end = entries_end,
+            gallery_script = if gallery { gallery::SCRIPT } else { "" },
+        );
+        let body: Box<dyn WriteBody> = Box::new(HtmlListingBody {
+            head,
+            entries,
+            directory,
+            base_url: base_url.to_owned(),
+            path_prefix: path_prefix.to_owned(),
+            checkboxes: mode == ListingMode::Directory,
+            previews: matches!(mode, ListingMode::Directory | ListingMode::Search { .. }),
+            gallery,
+            columns: extra_columns.to_vec(),
+            tail,
+        });
+        Ok(self.html_response(req, body))
+    }
 
+    /// Data given to the `--template` listing template
+    #[allow(clippy::too_many_arguments)]
+    fn listing_context(
+        &self,
+        entries: Vec<Entry>,
+        path_prefix: &[OsString],
+        base_url: &str,
+        mode: ListingMode,
+        sorting: Option<&(String, String)>,
+        dirs_first: bool,
+        link_params: &[(String, String)],
+        search: Option<(String, bool)>,
+        pagination: Option<Pagination>,
+        header: Option<String>,
+        readme: Option<String>,
+        gallery: bool,
+        has_media: bool,
+    ) -> ListingContext {
+        let previews = matches!(mode, ListingMode::Directory | ListingMode::Search { .. });
+        let mut breadcrumbs = vec![Link {
+            name: "".to_owned(),
+            href: base_url.to_owned(),
+        }];
+        for i in 0..path_prefix.len() {
+            let mut link = path_prefix[..=i].to_owned();
+            link.push(OsString::new());
+            breadcrumbs.push(Link {
+                name: path_prefix[i].to_string_lossy().into_owned(),
+                href: format!("{}{}", base_url, encode_link_path(&link)),
+            });
+        }
+        let parent = if path_prefix.is_empty() {
+            None
+        } else {
+            breadcrumbs
+                .get(breadcrumbs.len() - 2)
+                .map(|link| link.href.clone())
+        };
+        let mut current_link = path_prefix.to_owned();
+        current_link.push(OsString::new());
+        let current_href = format!("{}{}", base_url, encode_link_path(&current_link));
+
+        let sort_links = if self.sort && mode != ListingMode::DropBox {
+            let mut hrefs = sort_hrefs(
+                path_prefix,
+                base_url,
+                sorting,
+                &self.sort_order,
+                dirs_first,
+                link_params,
+            );
+            Some(SortLinks {
+                field: sorting.map(|(field, _)| field.clone()),
+                order: sorting.map(|(_, order)| order.clone()),
+                dirs_first,
+                name: hrefs.remove("name").unwrap(),
+                modified: hrefs.remove("modified").unwrap(),
+                size: hrefs.remove("size").unwrap(),
+                ext: hrefs.remove("ext").unwrap(),
+                kind: hrefs.remove("type").unwrap(),
+                dirsize: hrefs
+                    .remove("dirsize")
+                    .filter(|_| self.columns.has(Column::DirSize)),
+                toggle_dirs_first: hrefs.remove("dirs_first").unwrap(),
+            })
+        } else {
+            None
+        };
+        let upload = match self.upload {
+            Some(ref upload) if mode == ListingMode::Directory || mode == ListingMode::DropBox => {
+                Some(UploadForm {
+                    action: current_href.clone(),
+                    csrf_token: upload.csrf_token.clone(),
+                })
+            }
+            _ => None,
+        };
+        let downloads = if mode == ListingMode::Directory {
+            ArchiveFormat::ALL
+                .iter()
+                .map(|format| Link {
+                    name: format.extension().to_owned(),
+                    href: format!("{}?download={}", current_href, format.extension()),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        ListingContext {
+            base_url: base_url.to_owned(),
+            static_url: format!("{}{}/", base_url, template::STATIC_PATH),
+            path: display_path(path_prefix),
+            breadcrumbs,
+            parent,
+            entries: entries
+                .into_iter()
+                .map(|entry| TemplateEntry {
+                    href: entry.href(base_url, path_prefix),
+                    size: entry.len,
+                    size_label: if entry.is_dir {
+                        "-".to_owned()
+                    } else {
+                        convert(entry.len as f64)
+                    },
+                    modified: system_time_to_date_time(entry.modified)
+                        .format("%Y-%m-%d %H:%M:%S")
+                        .to_string(),
+                    mime: entry.mime(),
+                    symlink_target: entry
+                        .symlink
+                        .as_ref()
+                        .map(|target| target.to_string_lossy().to_string()),
+                    thumbnail: if mode == ListingMode::Directory
+                        && gallery::is_image(Path::new(&entry.filename))
+                    {
+                        Some(format!(
+                            "{}?thumb={}",
+                            entry.href(base_url, path_prefix),
+                            gallery::GALLERY_THUMB_WIDTH
+                        ))
+                    } else {
+                        None
+                    },
+                    preview: if previews
+                        && !entry.is_dir
+                        && Preview::from_path(Path::new(&entry.filename)).is_some()
+                    {
+                        Some(format!("{}?preview", entry.href(base_url, path_prefix)))
+                    } else {
+                        None
+                    },
+                    play: if previews
+                        && !entry.is_dir
+                        && player::is_media(Path::new(&entry.filename))
+                    {
+                        Some(format!("{}?play", entry.href(base_url, path_prefix)))
+                    } else {
+                        None
+                    },
+                    is_dir: entry.is_dir,
+                    name: entry.filename,
+                    columns: entry.columns,
+                })
+                .collect(),
+            columns: if mode == ListingMode::DropBox {
+                Vec::new()
+            } else {
+                self.columns
+                    .enabled()
+                    .iter()
+                    .map(|column| column.name())
+                    .collect()
+            },
+            sort_links,
+            csrf_token: upload.as_ref().map(|upload| upload.csrf_token.clone()),
+            upload,
+            downloads,
+            search_truncated: matches!(search, Some((_, true))),
+            search: search.map(|(pattern, _)| pattern),
+            pagination,
+            header,
+            readme,
+            gallery,
+            play_all: if has_media {
+                Some(format!("{}?play", current_href))
+            } else {
+                None
+            },
+            dropbox: mode == ListingMode::DropBox,
+            archive: mode == ListingMode::Archive,
+        }
+    }
+
+    /// HTML listing response, compressed when enabled
+    fn html_response<B: Modifier<Response>>(&self, req: &Request, body: B) -> Response {
+        let mut resp = Response::with((status::Ok, body));
         resp.headers.set(headers::ContentType::html());
         if self.compress.is_some() {
             if let Some(AcceptEncoding(encodings)) = req.headers.get::<AcceptEncoding>() {
@@ -928,6 +2532,386 @@ impl MainHandler {
                 }
             }
         }
+        resp
+    }
+
+    /// `?play`: player page of the audio or video file `current` of `dir`, or of all
+    /// of them
+    ///
+    /// The other media of the directory are only listed when listing it is allowed.
+    fn send_player(
+        &self,
+        req: &Request,
+        dir: &Path,
+        dir_link: &[OsString],
+        current: Option<&OsStr>,
+    ) -> IronResult<Response> {
+        if let Some(name) = current {
+            if !player::is_media(Path::new(name)) {
+                return Err(IronError::new(
+                    StringError("Not an audio or video file".to_owned()),
+                    status::BadRequest,
+                ));
+            }
+        }
+        let mut tracks =
+            player::playlist(dir, dir_link, &self.base_url, &self.filter, self.collation)
+                .map_err(error_io2iron)?;
+        if !self.listing_allowed(dir) {
+            tracks.retain(|track| Some(track.file_name.as_os_str()) == current);
+        }
+        if tracks.is_empty() {
+            return Err(IronError::new(
+                StringError("No audio or video files".to_owned()),
+                status::NotFound,
+            ));
+        }
+        let index = current
+            .and_then(|name| tracks.iter().position(|track| track.file_name == name))
+            .unwrap_or(0);
+        let mut link = dir_link.to_owned();
+        link.push(OsString::new());
+        let dir_url = format!("{}{}", self.base_url, encode_link_path(&link));
+        let title = format!("/{}", display_path(dir_link));
+        let html = player::render(&title, &dir_url, &tracks, index);
+        Ok(self.html_response(req, html))
+    }
+
+    /// `?thumb=WIDTH`: the image resized, cached on disk
+    fn send_thumbnail(&self, req: &Request, path: &Path, width: u32) -> IronResult<Response> {
+        if !gallery::is_image(path) {
+            return Err(IronError::new(
+                StringError("No thumbnail for this file type".to_owned()),
+                status::BadRequest,
+            ));
+        }
+        let thumbnail = self
+            .thumbnails
+            .get(path, width)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::InvalidData => IronError::new(err, status::UnsupportedMediaType),
+                _ => error_io2iron(err),
+            })?;
+        self.send_file(req, thumbnail, None)
+    }
+
+    /// `?preview`: Markdown rendered, source code highlighted, CSV and TSV as a table
+    fn send_preview(&self, req: &Request, path: &Path, path_prefix: &Path) -> IronResult<Response> {
+        let preview = Preview::from_path(path).ok_or_else(|| {
+            IronError::new(
+                StringError("No preview for this file type".to_owned()),
+                status::BadRequest,
+            )
+        })?;
+        let mut link: Vec<&OsStr> = path_prefix.iter().collect();
+        let raw_url = format!("{}{}", self.base_url, encode_link_path(&link));
+        link.pop();
+        link.push(OsStr::new(""));
+        let dir_url = format!("{}{}", self.base_url, encode_link_path(&link));
+        let html = preview::render(
+            preview,
+            path,
+            &path_prefix.to_string_lossy(),
+            &raw_url,
+            &dir_url,
+            &self.base_url,
+        )
+        .map_err(error_io2iron)?;
+        Ok(self.html_response(req, html))
+    }
+
+    /// `?follow`: viewer page of the text file, `?follow=events`: the text
+    /// appended to it as Server-Sent Events
+    fn send_follow(&self, req: &Request, path: &Path, path_prefix: &Path) -> IronResult<Response> {
+        if !follow::is_followable(path) {
+            return Err(IronError::new(
+                StringError("Only text files can be followed".to_owned()),
+                status::BadRequest,
+            ));
+        }
+        let lines = follow::tail_lines(req, self.follow.lines)?;
+        if follow::is_event_stream(req)? {
+            let resume = req
+                .headers
+                .get_raw("Last-Event-ID")
+                .and_then(|values| values.first())
+                .and_then(|value| std::str::from_utf8(value).ok())
+                .and_then(|value| value.trim().parse::<u64>().ok());
+            let body = self.follow.stream(path, lines, resume).ok_or_else(|| {
+                IronError::new(
+                    StringError(format!(
+                        "Too many followers (at most {})",
+                        self.follow.max_streams
+                    )),
+                    status::ServiceUnavailable,
+                )
+            })?;
+            let mut resp = Response::with(status::Ok);
+            resp.headers
+                .set_raw("content-type", vec![b"text/event-stream".to_vec()]);
+            resp.headers.set(headers::CacheControl(vec![
+                headers::CacheDirective::NoCache,
+            ]));
+            // Not buffered by reverse proxies
+            resp.headers
+                .set_raw("X-Accel-Buffering", vec![b"no".to_vec()]);
+            resp.body = Some(Box::new(body));
+            return Ok(resp);
+        }
+        let mut link: Vec<&OsStr> = path_prefix.iter().collect();
+        let raw_url = format!("{}{}", self.base_url, encode_link_path(&link));
+        link.pop();
+        link.push(OsStr::new(""));
+        let dir_url = format!("{}{}", self.base_url, encode_link_path(&link));
+        let html = follow::render(&path_prefix.to_string_lossy(), &raw_url, &dir_url, lines);
+        Ok(self.html_response(req, html))
+    }
+
+    fn send_template_asset(
+        &self,
+        req: &Request,
+        templates: &Templates,
+        segments: &[&str],
+    ) -> IronResult<Response> {
+        let not_found = || {
+            IronError::new(
+                io::Error::new(io::ErrorKind::NotFound, "Not Found"),
+                status::NotFound,
+            )
+        };
+        let mut path = templates.static_dir().to_path_buf();
+        for segment in segments {
+            let segment = percent_decode(segment.as_bytes())
+                .decode_utf8()
+                .map_err(|_| not_found())?;
+            path.push(&*segment);
+        }
+        // Confined to the static directory, symbolic links included
+        let static_dir = templates
+            .static_dir()
+            .canonicalize()
+            .map_err(|_| not_found())?;
+        match path.canonicalize() {
+            Ok(real_path) if real_path.starts_with(&static_dir) && real_path.is_file() => {
+                self.send_file(req, real_path, None)
+            }
+            _ => Err(not_found()),
+        }
+    }
+
+    /// List a directory of an archive or send one of its files
+    fn serve_archive(
+        &self,
+        req: &Request,
+        archive_path: &Path,
+        inner: &str,
+        path_prefix: &Path,
+    ) -> IronResult<Response> {
+        let kind = ArchiveKind::from_path(archive_path).unwrap();
+        let members = self
+            .archives
+            .members(kind, archive_path)
+            .map_err(error_io2iron)?;
+
+        if inner.is_empty() || archive::is_member_dir(&members, inner) {
+            if let Some(resp) = self.slash_redirect(req) {
+                return Ok(resp);
+            }
+            let children = archive::list_members(&members, inner);
+            for fname in &self.index_files {
+                if let Some(member) = children.iter().find(|m| !m.is_dir && m.name == *fname) {
+                    return self.send_archive_member(req, archive_path, kind, member);
+                }
+            }
+            // As the directory holding the archive
+            let archive_dir = archive_path.parent().unwrap_or(archive_path);
+            if !self.listing_allowed(archive_dir) {
+                return Err(IronError::new(
+                    StringError("Directory listing is disabled".to_owned()),
+                    status::Forbidden,
+                ));
+            }
+            let members_dir = archive_path.join(inner);
+            let entries = children
+                .into_iter()
+                .filter(|member| {
+                    !self
+                        .filter
+                        .is_filtered_entry(&members_dir.join(&member.name), member.is_dir)
+                })
+                .map(|member| Entry {
+                    filename: member.name,
+                    os_filename: None,
+                    is_dir: member.is_dir,
+                    len: member.size,
+                    modified: member.modified,
+                    symlink: None,
+                    path: None,
+                    columns: ColumnValues::default(),
+                })
+                .collect();
+            let path_prefix: Vec<OsString> = path_prefix.iter().map(OsStr::to_owned).collect();
+            return self.render_listing(
+                req,
+                entries,
+                None,
+                &path_prefix,
+                &self.base_url,
+                ListingMode::Archive,
+            );
+        }
+
+        match members.iter().find(|m| !m.is_dir && m.name == inner) {
+            Some(member) => self.send_archive_member(req, archive_path, kind, member),
+            None => Err(IronError::new(
+                StringError(format!("No such file in archive: {}", inner)),
+                status::NotFound,
+            )),
+        }
+    }
+
+    fn send_archive_member(
+        &self,
+        req: &Request,
+        archive_path: &Path,
+        kind: ArchiveKind,
+        member: &archive::Member,
+    ) -> IronResult<Response> {
+        use filetime::FileTime;
+        use iron::headers::{
+            AcceptRanges, ContentLength, ContentRange, ContentRangeSpec, EntityTag, RangeUnit,
+        };
+        use iron::method::Method;
+
+        let metadata = fs::metadata(archive_path).map_err(error_io2iron)?;
+        let time = FileTime::from_last_modification_time(&metadata);
+        let modified = time::Timespec::new(time.seconds() as i64, 0);
+        let etag = EntityTag::weak(format!(
+            "{0:x}-{1:x}-{2:x}.{3:x}",
+            member.size,
+            member.data_offset.unwrap_or(0),
+            modified.sec,
+            modified.nsec
+        ));
+
+        let mut resp = Response::with(status::Ok);
+        // Only members stored uncompressed can be seeked
+        let seekable = member.data_offset.is_some();
+        if self.range && seekable {
+            resp.headers.set(AcceptRanges(vec![RangeUnit::Bytes]));
+        }
+        let mime = mime_types::from_path(&member.name).first_or_octet_stream();
+        resp.headers
+            .set_raw("content-type", vec![mime.to_string().into_bytes()]);
+        match req.method {
+            Method::Head => resp.headers.set(ContentLength(member.size)),
+            Method::Get => {
+                let range = if seekable {
+                    self.requested_range(req, member.size, &etag, modified)?
+                } else {
+                    None
+                };
+                match (range, member.data_offset) {
+                    (Some((offset, length)), Some(data_offset)) => {
+                        let mut file = fs::File::open(archive_path).map_err(error_io2iron)?;
+                        file.seek(SeekFrom::Start(data_offset + offset))
+                            .map_err(error_io2iron)?;
+                        let take = file.take(length);
+
+                        resp.headers.set(ContentLength(length));
+                        resp.headers.set(ContentRange(ContentRangeSpec::Bytes {
+                            range: Some((offset, offset + length - 1)),
+                            instance_length: Some(member.size),
+                        }));
+                        resp.body = Some(Box::new(Box::new(take) as Box<dyn Read + Send>));
+                        resp.set_mut(status::PartialContent);
+                    }
+                    _ => {
+                        resp.headers.set(ContentLength(member.size));
+                        resp.body = Some(Box::new(MemberBody::new(
+                            kind,
+                            archive_path.to_owned(),
+                            member,
+                        )));
+                    }
+                }
+            }
+            _ => {
+                return Ok(Response::with(status::MethodNotAllowed));
+            }
+        }
+
+        if self.cache {
+            if self.is_not_modified(req, modified) {
+                return Ok(Response::with(status::NotModified));
+            }
+            self.set_cache_headers(&mut resp, modified, etag);
+        }
+        Ok(resp)
+    }
+
+    fn download_directory(
+        &self,
+        fs_path: &Path,
+        format: &str,
+        selected: Vec<String>,
+    ) -> IronResult<Response> {
+        let format = ArchiveFormat::from_query(format).ok_or_else(|| {
+            IronError::new(
+                StringError(format!("Unknown archive format: {}", format)),
+                status::BadRequest,
+            )
+        })?;
+        // Selected entries must be direct children of the directory
+        let mut names = Vec::with_capacity(selected.len());
+        for value in &selected {
+            let name = match decode_path_segment(value) {
+                Some(name)
+                    if !name.is_empty()
+                        && name != "."
+                        && name != ".."
+                        && !os_str_bytes(&name)
+                            .iter()
+                            .any(|b| *b == b'/' || *b == b'\\')
+                        && fs::symlink_metadata(fs_path.join(&name)).is_ok() =>
+                {
+                    name
+                }
+                _ => {
+                    return Err(IronError::new(
+                        StringError(format!("Invalid selected entry: {}", value)),
+                        status::BadRequest,
+                    ))
+                }
+            };
+            let path = fs_path.join(&name);
+            if self.filter.is_filtered_entry(&path, path.is_dir()) {
+                return Err(IronError::new(
+                    StringError(format!("Selected entry not found: {}", value)),
+                    status::NotFound,
+                ));
+            }
+            names.push(name);
+        }
+
+        let dirname = fs_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "root".to_owned());
+        let mut resp = Response::with(status::Ok);
+        resp.headers
+            .set_raw("content-type", vec![format.mime().as_bytes().to_vec()]);
+        resp.headers.set_raw(
+            "content-disposition",
+            vec![content_disposition(&format!("{}.{}", dirname, format.extension())).into_bytes()],
+        );
+        resp.body = Some(Box::new(DirectoryArchive::new(
+            format,
+            fs_path.to_owned(),
+            dirname,
+            if names.is_empty() { None } else { Some(names) },
+            Arc::clone(&self.filter),
+        )));
         Ok(resp)
     }
 
@@ -949,11 +2933,7 @@ impl MainHandler {
 
         use filetime::FileTime;
         use iron::headers::{
-            AcceptRanges, ByteRangeSpec, ContentLength, ContentRange, ContentRangeSpec, ETag,
-            EntityTag, IfMatch, IfRange, Range, RangeUnit,
-        };
-        use iron::headers::{
-            CacheControl, CacheDirective, HttpDate, IfModifiedSince, LastModified,
+            AcceptRanges, ContentLength, ContentRange, ContentRangeSpec, EntityTag, RangeUnit,
         };
         use iron::method::Method;
 
@@ -991,111 +2971,26 @@ impl MainHandler {
         }
         match req.method {
             Method::Head => resp.headers.set(ContentLength(metadata.len())),
-            Method::Get => {
-                if self.range {
-                    let mut range = req.headers.get::<Range>();
-
-                    if range.is_some() {
-                        // [Reference]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/If-Match
-                        // Check header::If-Match
-                        if let Some(IfMatch::Items(items)) = req.headers.get::<IfMatch>() {
-                            if !items.iter().any(|item| item.strong_eq(&etag)) {
-                                return Err(IronError::new(
-                                    StringError("Etag not matched".to_owned()),
-                                    status::RangeNotSatisfiable,
-                                ));
-                            }
-                        };
-                    }
-
-                    // [Reference]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/If-Range
-                    let matched_ifrange = match req.headers.get::<IfRange>() {
-                        Some(IfRange::EntityTag(etag_ifrange)) => etag.weak_eq(etag_ifrange),
-                        Some(IfRange::Date(HttpDate(date_ifrange))) => {
-                            time::at(modified) <= *date_ifrange
-                        }
-                        None => true,
-                    };
-                    if !matched_ifrange {
-                        range = None;
-                    }
-
-                    match range {
-                        Some(Range::Bytes(ranges)) => {
-                            if let Some(range) = ranges.first() {
-                                let (offset, length) = match *range {
-                                    ByteRangeSpec::FromTo(x, mut y) => {
-                                        // "x-y"
-                                        if x >= metadata.len() || x > y {
-                                            return Err(IronError::new(
-                                                StringError(format!(
-                                                    "Invalid range(x={}, y={})",
-                                                    x, y
-                                                )),
-                                                status::RangeNotSatisfiable,
-                                            ));
-                                        }
-                                        if y >= metadata.len() {
-                                            y = metadata.len() - 1;
-                                        }
-                                        (x, y - x + 1)
-                                    }
-                                    ByteRangeSpec::AllFrom(x) => {
-                                        // "x-"
-                                        if x >= metadata.len() {
-                                            return Err(IronError::new(
-                                                StringError(format!(
-                                                    "Range::AllFrom to large (x={}), Content-Length: {})",
-                                                    x, metadata.len())),
-                                                status::RangeNotSatisfiable
-                                            ));
-                                        }
-                                        (x, metadata.len() - x)
-                                    }
-                                    ByteRangeSpec::Last(mut x) => {
-                                        // "-x"
-                                        if x > metadata.len() {
-                                            x = metadata.len();
-                                        }
-                                        (metadata.len() - x, x)
-                                    }
-                                };
-                                let mut file = fs::File::open(path).map_err(error_io2iron)?;
-                                file.seek(SeekFrom::Start(offset)).map_err(error_io2iron)?;
-                                let take = file.take(length);
-
-                                resp.headers.set(ContentLength(length));
-                                resp.headers.set(ContentRange(ContentRangeSpec::Bytes {
-                                    range: Some((offset, offset + length - 1)),
-                                    instance_length: Some(metadata.len()),
-                                }));
-                                resp.body = Some(Box::new(Box::new(take) as Box<dyn Read + Send>));
-                                resp.set_mut(status::PartialContent);
-                            } else {
-                                return Err(IronError::new(
-                                    StringError("Empty range set".to_owned()),
-                                    status::RangeNotSatisfiable,
-                                ));
-                            }
-                        }
-                        Some(_) => {
-                            return Err(IronError::new(
-                                StringError("Invalid range type".to_owned()),
-                                status::RangeNotSatisfiable,
-                            ));
-                        }
-                        _ => {
-                            resp.headers.set(ContentLength(metadata.len()));
-                            let file = fs::File::open(path).map_err(error_io2iron)?;
-                            resp.body = Some(Box::new(file));
-                        }
-                    }
-                } else {
+            Method::Get => match self.requested_range(req, metadata.len(), &etag, modified)? {
+                Some((offset, length)) => {
+                    let mut file = fs::File::open(path).map_err(error_io2iron)?;
+                    file.seek(SeekFrom::Start(offset)).map_err(error_io2iron)?;
+                    let take = file.take(length);
+
+                    resp.headers.set(ContentLength(length));
+                    resp.headers.set(ContentRange(ContentRangeSpec::Bytes {
+                        range: Some((offset, offset + length - 1)),
+                        instance_length: Some(metadata.len()),
+                    }));
+                    resp.body = Some(Box::new(Box::new(take) as Box<dyn Read + Send>));
+                    resp.set_mut(status::PartialContent);
+                }
+                None => {
                     resp.headers.set(ContentLength(metadata.len()));
                     let file = fs::File::open(path).map_err(error_io2iron)?;
                     resp.body = Some(Box::new(file));
                 }
-            }
+            },
             _ => {
                 return Ok(Response::with(status::MethodNotAllowed));
             }
@@ -1118,20 +3013,131 @@ impl MainHandler {
         }
 
         if self.cache {
-            static SECONDS: u32 = 7 * 24 * 3600; // max-age: 7.days()
-            if let Some(&IfModifiedSince(HttpDate(ref if_modified_since))) =
-                req.headers.get::<IfModifiedSince>()
-            {
-                if modified <= if_modified_since.to_timespec() {
-                    return Ok(Response::with(status::NotModified));
+            if self.is_not_modified(req, modified) {
+                return Ok(Response::with(status::NotModified));
+            }
+            self.set_cache_headers(&mut resp, modified, etag);
+        }
+        Ok(resp)
+    }
+
+    /// Resolve the `Range` request header to (offset, length), `None` to send the whole content
+    fn requested_range(
+        &self,
+        req: &Request,
+        len: u64,
+        etag: &iron::headers::EntityTag,
+        modified: time::Timespec,
+    ) -> IronResult<Option<(u64, u64)>> {
+        use iron::headers::{ByteRangeSpec, HttpDate, IfMatch, IfRange, Range};
+
+        if !self.range {
+            return Ok(None);
+        }
+        let mut range = req.headers.get::<Range>();
+
+        if range.is_some() {
+            // [Reference]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/If-Match
+            // Check header::If-Match
+            if let Some(IfMatch::Items(items)) = req.headers.get::<IfMatch>() {
+                if !items.iter().any(|item| item.strong_eq(etag)) {
+                    return Err(IronError::new(
+                        StringError("Etag not matched".to_owned()),
+                        status::RangeNotSatisfiable,
+                    ));
                 }
             };
-            let cache = vec![CacheDirective::Public, CacheDirective::MaxAge(SECONDS)];
-            resp.headers.set(CacheControl(cache));
-            resp.headers.set(LastModified(HttpDate(time::at(modified))));
-            resp.headers.set(ETag(etag));
         }
-        Ok(resp)
+
+        // [Reference]: https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/If-Range
+        let matched_ifrange = match req.headers.get::<IfRange>() {
+            Some(IfRange::EntityTag(etag_ifrange)) => etag.weak_eq(etag_ifrange),
+            Some(IfRange::Date(HttpDate(date_ifrange))) => time::at(modified) <= *date_ifrange,
+            None => true,
+        };
+        if !matched_ifrange {
+            range = None;
+        }
+
+        match range {
+            Some(Range::Bytes(ranges)) => {
+                if let Some(range) = ranges.first() {
+                    let (offset, length) = match *range {
+                        ByteRangeSpec::FromTo(x, mut y) => {
+                            // "x-y"
+                            if x >= len || x > y {
+                                return Err(IronError::new(
+                                    StringError(format!("Invalid range(x={}, y={})", x, y)),
+                                    status::RangeNotSatisfiable,
+                                ));
+                            }
+                            if y >= len {
+                                y = len - 1;
+                            }
+                            (x, y - x + 1)
+                        }
+                        ByteRangeSpec::AllFrom(x) => {
+                            // "x-"
+                            if x >= len {
+                                return Err(IronError::new(
+                                    StringError(format!(
+                                        "Range::AllFrom to large (x={}), Content-Length: {})",
+                                        x, len
+                                    )),
+                                    status::RangeNotSatisfiable,
+                                ));
+                            }
+                            (x, len - x)
+                        }
+                        ByteRangeSpec::Last(mut x) => {
+                            // "-x"
+                            if x > len {
+                                x = len;
+                            }
+                            (len - x, x)
+                        }
+                    };
+                    Ok(Some((offset, length)))
+                } else {
+                    Err(IronError::new(
+                        StringError("Empty range set".to_owned()),
+                        status::RangeNotSatisfiable,
+                    ))
+                }
+            }
+            Some(_) => Err(IronError::new(
+                StringError("Invalid range type".to_owned()),
+                status::RangeNotSatisfiable,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether the client cached copy is still fresh (header::If-Modified-Since)
+    fn is_not_modified(&self, req: &Request, modified: time::Timespec) -> bool {
+        use iron::headers::{HttpDate, IfModifiedSince};
+
+        match req.headers.get::<IfModifiedSince>() {
+            Some(&IfModifiedSince(HttpDate(ref if_modified_since))) => {
+                modified <= if_modified_since.to_timespec()
+            }
+            None => false,
+        }
+    }
+
+    fn set_cache_headers(
+        &self,
+        resp: &mut Response,
+        modified: time::Timespec,
+        etag: iron::headers::EntityTag,
+    ) {
+        use iron::headers::{CacheControl, CacheDirective, ETag, HttpDate, LastModified};
+
+        static SECONDS: u32 = 7 * 24 * 3600; // max-age: 7.days()
+        let cache = vec![CacheDirective::Public, CacheDirective::MaxAge(SECONDS)];
+        resp.headers.set(CacheControl(cache));
+        resp.headers.set(LastModified(HttpDate(time::at(modified))));
+        resp.headers.set(ETag(etag));
     }
 }
 
@@ -35,6 +35,24 @@ impl AuthChecker {
     }
 }
 
+impl AuthChecker {
+    /// Whether the request carries the configured Basic Auth credentials
+    pub fn is_authorized(&self, req: &Request) -> bool {
+        use iron::headers::{Authorization, Basic};
+
+        match req.headers.get::<Authorization<Basic>>() {
+            Some(&Authorization(Basic {
+                ref username,
+                ref password,
+            })) => {
+                username == self.username.as_str()
+                    && password.as_deref() == Some(self.password.as_str())
+            }
+            None => false,
+        }
+    }
+}
+
 impl BeforeMiddleware for AuthChecker {
     fn before(&self, req: &mut Request) -> IronResult<()> {
         use iron::headers::{Authorization, Basic};
@@ -1,4 +1,5 @@
 use std::ops::Deref;
+use std::sync::Arc;
 
 use iron::status;
 use iron::{AfterMiddleware, IronError, IronResult, Request, Response};
@@ -7,6 +8,7 @@ use percent_encoding::percent_decode;
 use termcolor::{Color, ColorSpec};
 
 use crate::color::{build_spec, Printer};
+use crate::template::Templates;
 use crate::util::{error_resp, now_string};
 
 lazy_static! {
@@ -18,6 +20,7 @@ lazy_static! {
 pub struct RequestLogger {
     pub printer: Printer,
     pub base_url: String,
+    pub templates: Option<Arc<Templates>>,
 }
 
 impl RequestLogger {
@@ -84,6 +87,7 @@ impl AfterMiddleware for RequestLogger {
                 err.response.status.unwrap_or(status::InternalServerError),
                 err.error.to_string().as_str(),
                 &self.base_url,
+                self.templates.as_deref(),
             ))
         }
     }
@@ -0,0 +1,212 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use htmlescape::encode_minimal;
+use mime_guess as mime_types;
+use serde::Serialize;
+
+use crate::filter::EntryFilter;
+use crate::listing;
+use crate::sorting::Collation;
+use crate::util::encode_link_path;
+
+const SCRIPT: &str = include_str!("assets/player.js");
+
+/// Whether `path` is an audio or video file, by its extension
+pub fn is_media(path: &Path) -> bool {
+    mime_types::from_path(path)
+        .first()
+        .is_some_and(|mime| mime.type_() == "audio" || mime.type_() == "video")
+}
+
+#[derive(Serialize)]
+pub struct Subtitle {
+    pub label: String,
+    /// From `movie.<lang>.vtt`
+    pub lang: Option<String>,
+    pub href: String,
+}
+
+/// Audio or video file of a playlist
+#[derive(Serialize)]
+pub struct Track {
+    pub name: String,
+    #[serde(skip)]
+    pub file_name: OsString,
+    pub href: String,
+    pub video: bool,
+    /// WebVTT files next to the media: `movie.vtt` and `movie.<lang>.vtt` for `movie.mp4`
+    pub subtitles: Vec<Subtitle>,
+}
+
+/// The audio and video files of `dir` (linked as `dir_link`), in name order
+pub fn playlist(
+    dir: &Path,
+    dir_link: &[OsString],
+    base_url: &str,
+    filter: &EntryFilter,
+    collation: Collation,
+) -> io::Result<Vec<Track>> {
+    let mut media = Vec::new();
+    let mut subtitles = Vec::new();
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        let is_file = fs::metadata(&path).map(|m| m.is_file()).unwrap_or(false);
+        if !is_file || filter.is_filtered_entry(&path, false) {
+            continue;
+        }
+        let is_vtt = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("vtt"));
+        if is_vtt {
+            subtitles.push(entry.file_name());
+        } else if is_media(&path) {
+            media.push(entry.file_name());
+        }
+    }
+    media.sort_by(|a, b| collation.compare(&a.to_string_lossy(), &b.to_string_lossy()));
+    subtitles.sort();
+
+    let href = |name: &OsString| {
+        let mut link = dir_link.to_owned();
+        link.push(name.clone());
+        format!("{}{}", base_url, encode_link_path(&link))
+    };
+    Ok(media
+        .into_iter()
+        .map(|file_name| {
+            let path = Path::new(&file_name);
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            Track {
+                name: file_name.to_string_lossy().into_owned(),
+                href: href(&file_name),
+                video: mime_types::from_path(path)
+                    .first()
+                    .is_some_and(|mime| mime.type_() == "video"),
+                subtitles: subtitles
+                    .iter()
+                    .filter_map(|subtitle| {
+                        let name = subtitle.to_string_lossy();
+                        // `.vtt` is ASCII, the slice ends on a character boundary
+                        let rest = name.strip_prefix(&*stem)?;
+                        let lang = match rest.get(..rest.len().checked_sub(4)?)? {
+                            "" => None,
+                            lang => Some(lang.strip_prefix('.')?.to_owned()),
+                        };
+                        Some(Subtitle {
+                            label: lang.clone().unwrap_or_else(|| "Subtitles".to_owned()),
+                            lang,
+                            href: href(subtitle),
+                        })
+                    })
+                    .collect(),
+                file_name,
+            }
+        })
+        .collect())
+}
+
+/// Player page of `tracks`, starting at `current`
+///
+/// The tracks are switched in place by the script, the playlist and the previous
+/// and next links still work without it.
+pub fn render(title: &str, dir_url: &str, tracks: &[Track], current: usize) -> String {
+    let track = &tracks[current];
+    let subtitles = track
+        .subtitles
+        .iter()
+        .enumerate()
+        .map(|(i, subtitle)| {
+            format!(
+                r#"<track kind="subtitles" label="{label}" src="{href}"{lang}{default}/>"#,
+                label = encode_minimal(&subtitle.label),
+                href = encode_minimal(&subtitle.href),
+                lang = subtitle
+                    .lang
+                    .as_ref()
+                    .map(|lang| format!(r#" srclang="{}""#, encode_minimal(lang)))
+                    .unwrap_or_default(),
+                default = if i == 0 { " default" } else { "" },
+            )
+        })
+        .collect::<String>();
+    let neighbour = |index: Option<usize>, id: &str, label: &str| {
+        let track = index.and_then(|index| tracks.get(index));
+        format!(
+            r#"<a id="{id}" href="{href}"{hidden}>{label}</a>"#,
+            id = id,
+            href = track
+                .map(|track| encode_minimal(&format!("{}?play", track.href)))
+                .unwrap_or_default(),
+            hidden = if track.is_some() { "" } else { " hidden" },
+            label = label,
+        )
+    };
+    let playlist = tracks
+        .iter()
+        .enumerate()
+        .map(|(i, track)| {
+            format!(
+                r#"<li{current}><a href="{href}?play">{name}</a></li>"#,
+                current = if i == current {
+                    r#" class="current" aria-current="true""#
+                } else {
+                    ""
+                },
+                href = encode_minimal(&track.href),
+                name = encode_minimal(&track.name),
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    // Tracks for the script, `</` would end the script element
+    let tracks_json = serde_json::to_string(tracks)
+        .unwrap_or_else(|_| "[]".to_owned())
+        .replace("</", "<\\/");
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width,initial-scale=1.0"/>
+  <meta name="color-scheme" content="light dark"/>
+  <title>{name}</title>
+  <style>{style}</style>
+</head>
+<body>
+<header>
+  <nav class="breadcrumb" aria-label="Path">{title}</nav>
+  <div class="toolbar">
+    <a href="{dir_url}">Parent directory</a>
+  </div>
+</header>
+<main class="player">
+  <h1 id="track-name">{name}</h1>
+  <video id="player" class="{kind}" data-index="{current}" src="{href}" controls preload="metadata">{subtitles}</video>
+  <nav class="pagination" aria-label="Tracks">{prev} {next}</nav>
+  <ol class="playlist">
+{playlist}
+  </ol>
+</main>
+<script type="application/json" id="tracks">{tracks_json}</script>
+<script>{script}</script>
+</body>
+</html>
+"#,
+        name = encode_minimal(&track.name),
+        style = listing::STYLE,
+        title = encode_minimal(title),
+        dir_url = encode_minimal(dir_url),
+        kind = if track.video { "video" } else { "audio" },
+        current = current,
+        href = encode_minimal(&track.href),
+        subtitles = subtitles,
+        prev = neighbour(current.checked_sub(1), "prev", "&laquo; Previous"),
+        next = neighbour(Some(current + 1), "next", "Next &raquo;"),
+        playlist = playlist,
+        tracks_json = tracks_json,
+        script = SCRIPT,
+    )
+}
@@ -0,0 +1,220 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use htmlescape::encode_minimal;
+use lazy_static::lazy_static;
+use pretty_bytes::converter::convert;
+use syntect::highlighting::ThemeSet;
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::listing;
+use crate::readme::markdown_to_html;
+
+/// Only the beginning of larger Markdown and source files is shown
+const MAX_PREVIEW_SIZE: u64 = 1024 * 1024;
+/// Rows of CSV/TSV tables shown, after the header
+const CSV_ROW_LIMIT: usize = 1000;
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+const SCRIPT: &str = include_str!("assets/preview.js");
+
+lazy_static! {
+    static ref SYNTAXES: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    /// Highlighting colors, light and dark
+    static ref HIGHLIGHT_STYLE: String = {
+        let themes = ThemeSet::load_defaults();
+        let css = |name: &str| {
+            css_for_theme_with_class_style(&themes.themes[name], CLASS_STYLE).unwrap_or_default()
+        };
+        format!(
+            "{}\n@media (prefers-color-scheme: dark) {{\n{}\n}}",
+            css("InspiredGitHub"),
+            css("base16-ocean.dark")
+        )
+    };
+}
+
+/// How a file is rendered with `?preview`
+#[derive(Clone, Copy)]
+pub enum Preview {
+    Markdown,
+    /// Delimiter of the fields
+    Table(u8),
+    Code(&'static SyntaxReference),
+}
+
+impl Preview {
+    /// `None` for files without a preview (plain text, binaries)
+    pub fn from_path(path: &Path) -> Option<Preview> {
+        let ext = path.extension().and_then(|ext| ext.to_str())?;
+        match ext.to_lowercase().as_str() {
+            "md" | "markdown" => Some(Preview::Markdown),
+            "csv" => Some(Preview::Table(b',')),
+            "tsv" => Some(Preview::Table(b'\t')),
+            "txt" => None,
+            _ => SYNTAXES.find_syntax_by_extension(ext).map(Preview::Code),
+        }
+    }
+}
+
+/// HTML page previewing the file at `path`, named `title`
+///
+/// `raw_url` is the URL of the file itself, `dir_url` the one of its directory.
+pub fn render(
+    preview: Preview,
+    path: &Path,
+    title: &str,
+    raw_url: &str,
+    dir_url: &str,
+    base_url: &str,
+) -> io::Result<String> {
+    let file = File::open(path)?;
+    let (content, notice) = match preview {
+        Preview::Markdown => {
+            let (text, notice) = read_text(file)?;
+            let html = markdown_to_html(&text, dir_url, base_url);
+            (
+                format!(r#"<article class="readme">{}</article>"#, html),
+                notice,
+            )
+        }
+        Preview::Code(syntax) => {
+            let (text, notice) = read_text(file)?;
+            (highlight(&text, syntax), notice)
+        }
+        Preview::Table(delimiter) => render_table(file, delimiter),
+    };
+    Ok(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width,initial-scale=1.0"/>
+  <meta name="color-scheme" content="light dark"/>
+  <title>{title}</title>
+  <style>{style}
+{highlight_style}</style>
+</head>
+<body>
+<header>
+  <nav class="breadcrumb" aria-label="Path">{title}</nav>
+  <div class="toolbar">
+    <a href="{dir_url}">Parent directory</a>
+    <a href="{raw_url}">Raw</a>
+  </div>
+</header>
+<main class="preview">
+  {notice}
+  {content}
+</main>
+<script>{script}</script>
+</body>
+</html>
+"#,
+        title = encode_minimal(title),
+        style = listing::STYLE,
+        highlight_style = *HIGHLIGHT_STYLE,
+        dir_url = encode_minimal(dir_url),
+        raw_url = encode_minimal(raw_url),
+        notice = notice
+            .map(|notice| format!(r#"<p class="notice">{}</p>"#, encode_minimal(&notice)))
+            .unwrap_or_default(),
+        content = content,
+        script = SCRIPT,
+    ))
+}
+
+/// The first `MAX_PREVIEW_SIZE` bytes, and a notice when the file is larger
+fn read_text(file: File) -> io::Result<(String, Option<String>)> {
+    let truncated = file.metadata()?.len() > MAX_PREVIEW_SIZE;
+    let mut bytes = Vec::new();
+    file.take(MAX_PREVIEW_SIZE).read_to_end(&mut bytes)?;
+    let notice = if truncated {
+        Some(format!(
+            "Only the first {} are shown",
+            convert(MAX_PREVIEW_SIZE as f64)
+        ))
+    } else {
+        None
+    };
+    Ok((String::from_utf8_lossy(&bytes).into_owned(), notice))
+}
+
+fn highlight(text: &str, syntax: &SyntaxReference) -> String {
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+    for line in LinesWithEndings::from(text) {
+        if generator
+            .parse_html_for_line_which_includes_newline(line)
+            .is_err()
+        {
+            // Unexpected grammar failure, fall back to plain text
+            return format!(r#"<pre class="hl-code">{}</pre>"#, encode_minimal(text));
+        }
+    }
+    format!(r#"<pre class="hl-code">{}</pre>"#, generator.finalize())
+}
+
+/// Table of the first `CSV_ROW_LIMIT` rows within the first `MAX_PREVIEW_SIZE`
+/// bytes, and a notice when some are missing
+fn render_table(file: File, delimiter: u8) -> (String, Option<String>) {
+    let truncated = file
+        .metadata()
+        .is_ok_and(|metadata| metadata.len() > MAX_PREVIEW_SIZE);
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(io::BufReader::new(file.take(MAX_PREVIEW_SIZE)));
+    let cells = |record: &csv::ByteRecord, tag: &str| {
+        record
+            .iter()
+            .map(|field| {
+                format!(
+                    "<{tag}>{}</{tag}>",
+                    encode_minimal(&String::from_utf8_lossy(field)),
+                    tag = tag
+                )
+            })
+            .collect::<String>()
+    };
+    let header = match reader.byte_headers() {
+        Ok(header) => cells(header, "th"),
+        Err(e) => return ("".to_owned(), Some(format!("Invalid table: {}", e))),
+    };
+    let mut rows = Vec::new();
+    let mut notice = None;
+    for record in reader.byte_records() {
+        match record {
+            Ok(_) if rows.len() == CSV_ROW_LIMIT => {
+                notice = Some(format!("Only the first {} rows are shown", CSV_ROW_LIMIT));
+                break;
+            }
+            Ok(record) => rows.push(format!("<tr>{}</tr>", cells(&record, "td"))),
+            Err(e) => {
+                notice = Some(format!("Stopped at an invalid row: {}", e));
+                break;
+            }
+        }
+    }
+    if notice.is_none() && truncated {
+        // The last row is cut by the size limit
+        rows.pop();
+        notice = Some(format!(
+            "Only the first {} are shown",
+            convert(MAX_PREVIEW_SIZE as f64)
+        ));
+    }
+    let table = format!(
+        r#"<table class="listing csv">
+  <thead><tr>{}</tr></thead>
+  <tbody>
+{}
+  </tbody>
+</table>"#,
+        header,
+        rows.join("\n")
+    );
+    (table, notice)
+}
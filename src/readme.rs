@@ -0,0 +1,124 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use htmlescape::encode_minimal;
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+
+use crate::filter::EntryFilter;
+
+/// Larger files are not rendered
+const MAX_README_SIZE: u64 = 1024 * 1024;
+
+/// URL schemes kept in links, others are replaced by `#`
+const LINK_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+/// URL schemes kept in image sources
+const IMAGE_SCHEMES: [&str; 2] = ["http", "https"];
+
+/// Files rendered above and below directory listings (`--readme`)
+pub struct Readme {
+    /// Candidates of the header, first existing one wins
+    pub header_names: Vec<String>,
+    /// Candidates of the footer
+    pub footer_names: Vec<String>,
+}
+
+impl Readme {
+    /// Sanitized HTML of the header and footer of `dir`, whose URL is `dir_url`
+    pub fn render(
+        &self,
+        dir: &Path,
+        dir_url: &str,
+        base_url: &str,
+        filter: &EntryFilter,
+    ) -> (Option<String>, Option<String>) {
+        let render = |names: &[String]| {
+            find_file(dir, names, filter).and_then(|path| render_file(&path, dir_url, base_url))
+        };
+        (render(&self.header_names), render(&self.footer_names))
+    }
+}
+
+fn find_file(dir: &Path, names: &[String], filter: &EntryFilter) -> Option<PathBuf> {
+    names
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file() && !filter.is_filtered_entry(path, false))
+}
+
+/// Markdown (`.md`, `.markdown`) as HTML, anything else as preformatted text
+fn render_file(path: &Path, dir_url: &str, base_url: &str) -> Option<String> {
+    let file = fs::File::open(path).ok()?;
+    if file.metadata().ok()?.len() > MAX_README_SIZE {
+        return None;
+    }
+    let mut text = String::new();
+    file.take(MAX_README_SIZE).read_to_string(&mut text).ok()?;
+    let is_markdown = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("markdown"));
+    if is_markdown {
+        Some(markdown_to_html(&text, dir_url, base_url))
+    } else {
+        Some(format!("<pre>{}</pre>", encode_minimal(&text)))
+    }
+}
+
+/// Raw HTML is escaped, links and images are resolved against `dir_url` and
+/// `base_url` and restricted to safe schemes
+pub fn markdown_to_html(text: &str, dir_url: &str, base_url: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    let parser = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(html) => Event::Text(html),
+        Event::Start(Tag::Link(kind, dest, title)) => Event::Start(Tag::Link(
+            kind,
+            resolve_url(&dest, dir_url, base_url, &LINK_SCHEMES).into(),
+            title,
+        )),
+        Event::Start(Tag::Image(kind, dest, title)) => Event::Start(Tag::Image(
+            kind,
+            resolve_url(&dest, dir_url, base_url, &IMAGE_SCHEMES).into(),
+            title,
+        )),
+        event => event,
+    });
+    let mut html = String::new();
+    html::push_html(&mut html, parser);
+    html
+}
+
+fn resolve_url(dest: &str, dir_url: &str, base_url: &str, schemes: &[&str]) -> String {
+    if let Some(scheme) = url_scheme(dest) {
+        return if schemes.contains(&scheme.as_str()) {
+            dest.to_owned()
+        } else {
+            "#".to_owned()
+        };
+    }
+    if dest.is_empty() || dest.starts_with('#') || dest.starts_with("//") {
+        dest.to_owned()
+    } else if let Some(path) = dest.strip_prefix('/') {
+        format!("{}/{}", base_url.trim_end_matches('/'), path)
+    } else {
+        format!("{}{}", dir_url, dest)
+    }
+}
+
+/// Lowercase scheme of an absolute URL, ignoring the whitespace and control
+/// characters browsers skip (`java\tscript:`)
+fn url_scheme(url: &str) -> Option<String> {
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control())
+        .collect();
+    let end = url.find([':', '/', '?', '#'])?;
+    if url[end..].starts_with(':') {
+        Some(url[..end].to_ascii_lowercase())
+    } else {
+        None
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use glob::{MatchOptions, Pattern};
+use iron::status;
+use iron::{IronError, IronResult, Request};
+
+use crate::columns::ColumnValues;
+use crate::filter::EntryFilter;
+use crate::listing::Entry;
+use crate::util::StringError;
+
+/// Default and maximum number of search results (`?limit=N`)
+const SEARCH_LIMIT: usize = 1000;
+const MAX_SEARCH_LIMIT: usize = 10_000;
+/// Stop walking the tree after this long and return what was found
+pub const SEARCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Matcher {
+    /// Pattern with `*`, `?` or `[...]`, matched against the whole filename
+    Glob(Pattern),
+    /// Lowercased substring of the filename
+    Substring(String),
+}
+
+/// `?depth=N` of directory walks, 1 for the directory itself
+pub fn max_depth(req: &Request) -> IronResult<Option<usize>> {
+    match req.url.as_ref().query_pairs().find(|(k, _)| k == "depth") {
+        Some((_, v)) => v.parse::<usize>().map(Some).map_err(|_| {
+            IronError::new(
+                StringError(format!("Invalid depth: {}", v)),
+                status::BadRequest,
+            )
+        }),
+        None => Ok(None),
+    }
+}
+
+/// A `?search=PATTERN[&depth=N][&limit=N]` query
+pub struct SearchQuery {
+    pub pattern: String,
+    matcher: Matcher,
+    pub max_depth: Option<usize>,
+    pub limit: usize,
+}
+
+impl SearchQuery {
+    pub fn from_request(req: &Request) -> IronResult<Option<SearchQuery>> {
+        let mut pattern = None;
+        let max_depth = max_depth(req)?;
+        let mut limit = SEARCH_LIMIT;
+        for (k, v) in req.url.as_ref().query_pairs() {
+            if k == "search" {
+                pattern = Some(v.trim().to_owned());
+            } else if k == "limit" {
+                limit = match v.parse::<usize>() {
+                    Ok(n) if n > 0 && n <= MAX_SEARCH_LIMIT => n,
+                    _ => {
+                        return Err(IronError::new(
+                            StringError(format!(
+                                "Invalid limit: {} (1 to {} expected)",
+                                v, MAX_SEARCH_LIMIT
+                            )),
+                            status::BadRequest,
+                        ))
+                    }
+                };
+            }
+        }
+        let pattern = match pattern {
+            Some(pattern) if !pattern.is_empty() => pattern,
+            _ => return Ok(None),
+        };
+        let matcher = if pattern.contains(['*', '?', '[']) {
+            Matcher::Glob(Pattern::new(&pattern).map_err(|err| {
+                IronError::new(
+                    StringError(format!("Invalid search pattern: {}", err)),
+                    status::BadRequest,
+                )
+            })?)
+        } else {
+            Matcher::Substring(pattern.to_lowercase())
+        };
+        Ok(Some(SearchQuery {
+            pattern,
+            matcher,
+            max_depth,
+            limit,
+        }))
+    }
+
+    pub fn is_glob(&self) -> bool {
+        matches!(self.matcher, Matcher::Glob(_))
+    }
+
+    /// Case-insensitive match of a filename
+    fn matches(&self, filename: &str) -> bool {
+        match self.matcher {
+            Matcher::Glob(ref pattern) => pattern.matches_with(
+                filename,
+                MatchOptions {
+                    case_sensitive: false,
+                    require_literal_separator: false,
+                    require_literal_leading_dot: false,
+                },
+            ),
+            Matcher::Substring(ref s) => filename.to_lowercase().contains(s.as_str()),
+        }
+    }
+}
+
+/// Walk `dir` breadth first and collect the entries matching `query`
+///
+/// Entry filenames are paths relative to `dir` (`a/b/c.txt`), symbolic links are
+/// listed but not followed. Filtered entries are skipped with their content. The
+/// flag is set when the limit or the timeout cut the search short.
+pub fn search(
+    dir: &Path,
+    query: &SearchQuery,
+    filter: &EntryFilter,
+) -> io::Result<(Vec<Entry>, bool)> {
+    let deadline = Instant::now() + SEARCH_TIMEOUT;
+    let mut results = Vec::new();
+    let mut pending = VecDeque::new();
+    pending.push_back((dir.to_path_buf(), PathBuf::new(), 1));
+    while let Some((path, prefix, depth)) = pending.pop_front() {
+        let read_dir = match fs::read_dir(&path) {
+            Ok(read_dir) => read_dir,
+            // Only the top directory must be readable
+            Err(_) if !prefix.as_os_str().is_empty() => continue,
+            Err(err) => return Err(err),
+        };
+        // Names that are not valid UTF-8 are matched by their lossy rendering
+        let mut children = read_dir
+            .flatten()
+            .map(|entry| {
+                let filename = entry.file_name().to_string_lossy().into_owned();
+                (entry, filename)
+            })
+            .collect::<Vec<_>>();
+        children.sort_by(|a, b| a.1.cmp(&b.1));
+        for (entry, filename) in children {
+            if Instant::now() > deadline {
+                return Ok((results, true));
+            }
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if filter.is_filtered_entry(&entry.path(), metadata.is_dir()) {
+                continue;
+            }
+            let name = prefix.join(entry.file_name());
+            if metadata.is_dir() && !matches!(query.max_depth, Some(max) if depth >= max) {
+                pending.push_back((entry.path(), name.clone(), depth + 1));
+            }
+            if query.matches(&filename) {
+                if results.len() == query.limit {
+                    return Ok((results, true));
+                }
+                let symlink = if metadata.file_type().is_symlink() {
+                    fs::read_link(entry.path()).ok()
+                } else {
+                    None
+                };
+                let (filename, os_filename) = Entry::split_name(name.into_os_string());
+                results.push(Entry {
+                    filename,
+                    os_filename,
+                    is_dir: metadata.is_dir(),
+                    len: metadata.len(),
+                    modified: metadata.modified().unwrap_or(std::time::UNIX_EPOCH),
+                    symlink,
+                    path: Some(entry.path()),
+                    columns: ColumnValues::default(),
+                });
+            }
+        }
+    }
+    Ok((results, false))
+}
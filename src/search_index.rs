@@ -0,0 +1,562 @@
+use std::collections::BTreeSet;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use mime_guess as mime_types;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::columns::ColumnValues;
+use crate::filter::EntryFilter;
+use crate::listing::Entry;
+use crate::search::SearchQuery;
+use crate::util::{decode_path_segment, os_str_bytes};
+
+/// Text of files up to this size is indexed with `--search-index-content`
+const MAX_CONTENT_SIZE: u64 = 1024 * 1024;
+/// Rows written per transaction while scanning
+const SCAN_BATCH: usize = 10_000;
+/// Filesystem events are collected for this long before updating the index
+const WATCH_DELAY: Duration = Duration::from_millis(500);
+
+/// Files of a SQLite database: the database itself, then its journals
+const DB_FILE_SUFFIXES: [&str; 4] = ["", "-wal", "-shm", "-journal"];
+
+const SCHEMA: &str = r#"
+PRAGMA journal_mode = WAL;
+CREATE TABLE IF NOT EXISTS files (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    depth INTEGER NOT NULL,
+    is_dir INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    mtime INTEGER NOT NULL,
+    scan INTEGER NOT NULL
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS files_fts USING fts5(name, content);
+"#;
+
+/// Persistent filename (and optionally full-text) index of the served directory
+///
+/// Paths are stored relative to the root (`a/b/c.txt`), see `encode_path` for
+/// names that are not valid UTF-8. The index is brought up to
+/// date by a background scan at startup, then incrementally by filesystem events.
+/// Searches fall back to walking the tree until the first scan is complete.
+pub struct SearchIndex {
+    /// Connection of the request threads, the writer thread has its own
+    reader: Mutex<Connection>,
+    ready: Arc<AtomicBool>,
+    root: PathBuf,
+    filter: Arc<EntryFilter>,
+}
+
+impl SearchIndex {
+    pub fn open(
+        db_path: &Path,
+        root: &Path,
+        content: bool,
+        filter: Arc<EntryFilter>,
+    ) -> Result<SearchIndex, String> {
+        // Inside the root, the database and its `-wal`/`-shm`/`-journal` files are
+        // filtered before they exist, they are neither served nor indexed
+        let parent = match db_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let resolved = db_path
+            .canonicalize()
+            .or_else(|_| {
+                parent
+                    .canonicalize()
+                    .map(|dir| dir.join(db_path.file_name().unwrap_or_default()))
+            })
+            .map_err(|e| format!("Open search index {}: {}", db_path.display(), e))?;
+        if resolved.starts_with(root) {
+            for suffix in DB_FILE_SUFFIXES {
+                let mut path = resolved.clone().into_os_string();
+                path.push(suffix);
+                filter.deny(PathBuf::from(path));
+            }
+        }
+        let writer = Connection::open(db_path)
+            .and_then(|conn| conn.execute_batch(SCHEMA).map(|_| conn))
+            .map_err(|e| format!("Open search index {}: {}", db_path.display(), e))?;
+        let reader = Connection::open(db_path)
+            .map_err(|e| format!("Open search index {}: {}", db_path.display(), e))?;
+
+        let mut indexer = Indexer {
+            conn: writer,
+            root: root.to_path_buf(),
+            content,
+            filter: Arc::clone(&filter),
+            scan: 0,
+        };
+        let ready = Arc::new(AtomicBool::new(false));
+        let ready_flag = Arc::clone(&ready);
+        thread::spawn(move || {
+            let (tx, rx) = channel();
+            // Watch first so nothing changed during the scan is missed
+            let watcher = notify::recommended_watcher(tx).and_then(|mut watcher| {
+                watcher
+                    .watch(&indexer.root, RecursiveMode::Recursive)
+                    .map(|_| watcher)
+            });
+            let watcher = match watcher {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    eprintln!("Search index: filesystem watching disabled: {}", e);
+                    None
+                }
+            };
+            if let Err(e) = indexer.scan() {
+                eprintln!("Search index: scan failed: {}", e);
+                return;
+            }
+            ready_flag.store(true, Ordering::SeqCst);
+            if let Some(watcher) = watcher {
+                indexer.watch(watcher, rx);
+            }
+        });
+        Ok(SearchIndex {
+            reader: Mutex::new(reader),
+            ready,
+            root: root.to_path_buf(),
+            filter,
+        })
+    }
+
+    /// Search under the root relative directory `dir` (empty for the root)
+    ///
+    /// Returns `None` when the index is not ready yet, otherwise the ranked matches
+    /// named relative to `dir` and whether the result limit cut them short.
+    pub fn search(
+        &self,
+        dir: &[OsString],
+        query: &SearchQuery,
+    ) -> Option<rusqlite::Result<(Vec<Entry>, bool)>> {
+        if !self.ready.load(Ordering::SeqCst) {
+            return None;
+        }
+        let conn = self.reader.lock().unwrap();
+        // Filters (e.g. ignore files) may have changed since the entries were indexed
+        let dir_path: PathBuf = self.root.join(dir.iter().collect::<PathBuf>());
+        let dir = encode_path(dir.iter().map(OsString::as_os_str));
+        Some(
+            run_query(&conn, &dir, query).map(|(mut entries, truncated)| {
+                entries.retain_mut(|entry| {
+                    let path = match entry.os_filename {
+                        Some(ref name) => dir_path.join(name),
+                        None => dir_path.join(&entry.filename),
+                    };
+                    let filtered = self.filter.is_filtered(&path, entry.is_dir);
+                    entry.path = Some(path);
+                    !filtered
+                });
+                (entries, truncated)
+            }),
+        )
+    }
+}
+
+fn run_query(
+    conn: &Connection,
+    dir: &str,
+    query: &SearchQuery,
+) -> rusqlite::Result<(Vec<Entry>, bool)> {
+    let prefix = if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", dir)
+    };
+    let base_depth = if dir.is_empty() {
+        0
+    } else {
+        dir.split('/').count() as i64
+    };
+    let max_depth = query.max_depth.map_or(-1, |depth| depth as i64);
+    let limit = query.limit as i64 + 1;
+    let pattern = query.pattern.to_lowercase();
+
+    let in_dir = "substr(f.path, 1, length(?2)) = ?2 AND (?3 < 0 OR f.depth - ?4 <= ?3)";
+    let (sql, terms) = if query.is_glob() {
+        (
+            format!(
+                "SELECT f.path, f.is_dir, f.size, f.mtime FROM files f
+                 WHERE lower(f.name) GLOB ?1 AND {}
+                 ORDER BY f.path LIMIT ?5",
+                in_dir
+            ),
+            String::new(),
+        )
+    } else {
+        // Exact names first, then filename substrings, then full-text matches by relevance
+        let terms = fts_terms(&pattern);
+        let fts_matches = if terms.is_empty() {
+            "SELECT NULL AS rowid, NULL AS score WHERE 0"
+        } else {
+            "SELECT rowid, bm25(files_fts, 10.0, 1.0) AS score FROM files_fts WHERE files_fts MATCH ?6"
+        };
+        (
+            format!(
+                "WITH m AS ({})
+                 SELECT f.path, f.is_dir, f.size, f.mtime FROM files f
+                 LEFT JOIN m ON m.rowid = f.id
+                 WHERE (m.rowid IS NOT NULL OR instr(lower(f.name), ?1) > 0) AND {}
+                 ORDER BY lower(f.name) = ?1 DESC, instr(lower(f.name), ?1) > 0 DESC,
+                          m.score IS NULL, m.score, f.path
+                 LIMIT ?5",
+                fts_matches, in_dir
+            ),
+            terms,
+        )
+    };
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = if terms.is_empty() {
+        stmt.query(params![pattern, prefix, max_depth, base_depth, limit])?
+    } else {
+        stmt.query(params![
+            pattern, prefix, max_depth, base_depth, limit, terms
+        ])?
+    };
+    let mut entries = Vec::new();
+    while let Some(row) = rows.next()? {
+        let path: String = row.get(0)?;
+        let mtime: i64 = row.get(3)?;
+        let name = match decode_path_segment(&path[prefix.len()..]) {
+            Some(name) => name,
+            None => continue,
+        };
+        let (filename, os_filename) = Entry::split_name(name);
+        entries.push(Entry {
+            filename,
+            os_filename,
+            is_dir: row.get(1)?,
+            len: row.get::<_, i64>(2)? as u64,
+            modified: UNIX_EPOCH + Duration::from_secs(mtime.max(0) as u64),
+            symlink: None,
+            path: None,
+            columns: ColumnValues::default(),
+        });
+    }
+    let truncated = entries.len() > query.limit;
+    entries.truncate(query.limit);
+    Ok((entries, truncated))
+}
+
+/// Path segments joined with `/` as stored in the index: `%` and the bytes of
+/// names that are not valid UTF-8 are percent-encoded, so every name has its
+/// own row and can be found again on disk
+fn encode_path<'a, I: Iterator<Item = &'a OsStr>>(segments: I) -> String {
+    let mut encoded = String::new();
+    for (i, segment) in segments.enumerate() {
+        if i > 0 {
+            encoded.push('/');
+        }
+        let mut bytes = &*os_str_bytes(segment);
+        while !bytes.is_empty() {
+            let (valid, invalid) = match std::str::from_utf8(bytes) {
+                Ok(valid) => (valid, 0),
+                Err(e) => (
+                    std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+                    e.error_len().unwrap_or(bytes.len() - e.valid_up_to()),
+                ),
+            };
+            encoded.push_str(&valid.replace('%', "%25"));
+            bytes = &bytes[valid.len()..];
+            for byte in &bytes[..invalid] {
+                write!(encoded, "%{:02X}", byte).unwrap();
+            }
+            bytes = &bytes[invalid..];
+        }
+    }
+    encoded
+}
+
+/// FTS5 query matching every word of `pattern` as a prefix
+fn fts_terms(pattern: &str) -> String {
+    pattern
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| format!("\"{}\"*", term))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Writer side of the index, owned by the background thread
+struct Indexer {
+    conn: Connection,
+    root: PathBuf,
+    content: bool,
+    filter: Arc<EntryFilter>,
+    /// Generation of the current scan, rows not seen by the scan are stale
+    scan: i64,
+}
+
+impl Indexer {
+    /// Stored root relative path, `None` for the root itself
+    fn relative(&self, path: &Path) -> Option<String> {
+        let rel = path.strip_prefix(&self.root).ok()?;
+        let rel = encode_path(rel.iter());
+        if rel.is_empty() {
+            None
+        } else {
+            Some(rel)
+        }
+    }
+
+    fn scan(&mut self) -> rusqlite::Result<()> {
+        self.scan =
+            self.conn
+                .query_row("SELECT coalesce(max(scan), 0) + 1 FROM files", [], |row| {
+                    row.get(0)
+                })?;
+        let mut pending = vec![self.root.clone()];
+        let mut batch = Vec::new();
+        while let Some(dir) = pending.pop() {
+            let read_dir = match fs::read_dir(&dir) {
+                Ok(read_dir) => read_dir,
+                Err(_) => continue,
+            };
+            for entry in read_dir.flatten() {
+                let path = entry.path();
+                if let Ok(metadata) = entry.metadata() {
+                    if self.filter.is_filtered_entry(&path, metadata.is_dir()) {
+                        continue;
+                    }
+                    if metadata.is_dir() {
+                        pending.push(path.clone());
+                    }
+                    batch.push((path, metadata));
+                }
+                if batch.len() >= SCAN_BATCH {
+                    self.write_batch(&mut batch)?;
+                }
+            }
+        }
+        self.write_batch(&mut batch)?;
+        // Remove what disappeared since the last run
+        self.conn.execute(
+            "DELETE FROM files_fts WHERE rowid IN (SELECT id FROM files WHERE scan != ?1)",
+            params![self.scan],
+        )?;
+        self.conn
+            .execute("DELETE FROM files WHERE scan != ?1", params![self.scan])?;
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: &mut Vec<(PathBuf, fs::Metadata)>) -> rusqlite::Result<()> {
+        let rows: Vec<_> = batch
+            .drain(..)
+            .filter_map(|(path, metadata)| Some((self.relative(&path)?, path, metadata)))
+            .collect();
+        let tx = self.conn.transaction()?;
+        for (rel, path, metadata) in rows {
+            upsert(&tx, &rel, &path, &metadata, self.content, self.scan)?;
+        }
+        tx.commit()
+    }
+
+    /// Apply filesystem events until the watcher goes away
+    fn watch(&mut self, _watcher: RecommendedWatcher, rx: Receiver<notify::Result<notify::Event>>) {
+        while let Ok(event) = rx.recv() {
+            let mut paths = BTreeSet::new();
+            if let Ok(event) = event {
+                paths.extend(event.paths);
+            }
+            // Collect a burst of events (e.g. a file being written) into one update
+            loop {
+                match rx.recv_timeout(WATCH_DELAY) {
+                    Ok(Ok(event)) => paths.extend(event.paths),
+                    Ok(Err(_)) => {}
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+            if let Err(e) = self.update(paths) {
+                eprintln!("Search index: update failed: {}", e);
+            }
+        }
+    }
+
+    fn update(&mut self, paths: BTreeSet<PathBuf>) -> rusqlite::Result<()> {
+        let mut batch = Vec::new();
+        let mut removed = Vec::new();
+        for path in paths {
+            let rel = match self.relative(&path) {
+                Some(rel) => rel,
+                None => continue,
+            };
+            match fs::symlink_metadata(&path) {
+                Ok(ref metadata) if self.filter.is_filtered(&path, metadata.is_dir()) => {}
+                Ok(metadata) => {
+                    // A directory moved in: index everything below it
+                    if metadata.is_dir() {
+                        let mut pending = vec![path.clone()];
+                        while let Some(dir) = pending.pop() {
+                            for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+                                if let Ok(metadata) = entry.metadata() {
+                                    if self
+                                        .filter
+                                        .is_filtered_entry(&entry.path(), metadata.is_dir())
+                                    {
+                                        continue;
+                                    }
+                                    if metadata.is_dir() {
+                                        pending.push(entry.path());
+                                    }
+                                    batch.push((entry.path(), metadata));
+                                }
+                            }
+                        }
+                    }
+                    batch.push((path, metadata));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => removed.push(rel),
+                Err(_) => {}
+            }
+        }
+        let tx = self.conn.transaction()?;
+        for rel in removed {
+            // The path and everything below it
+            let below = format!("{}/", rel);
+            tx.execute(
+                "DELETE FROM files_fts WHERE rowid IN
+                 (SELECT id FROM files WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2)",
+                params![rel, below],
+            )?;
+            tx.execute(
+                "DELETE FROM files WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+                params![rel, below],
+            )?;
+        }
+        tx.commit()?;
+        self.write_batch(&mut batch)
+    }
+}
+
+/// Insert or refresh one entry, the text is only read again when size or mtime changed
+fn upsert(
+    conn: &Connection,
+    rel: &str,
+    path: &Path,
+    metadata: &fs::Metadata,
+    content: bool,
+    scan: i64,
+) -> rusqlite::Result<()> {
+    let size = metadata.len() as i64;
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as i64);
+    let existing: Option<(i64, i64, i64)> = conn
+        .query_row(
+            "SELECT id, size, mtime FROM files WHERE path = ?1",
+            params![rel],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    if let Some((id, old_size, old_mtime)) = existing {
+        if old_size == size && old_mtime == mtime {
+            conn.execute(
+                "UPDATE files SET scan = ?1 WHERE id = ?2",
+                params![scan, id],
+            )?;
+            return Ok(());
+        }
+        conn.execute("DELETE FROM files_fts WHERE rowid = ?1", params![id])?;
+    }
+    // Names are matched in their readable form
+    let name = rel.rsplit('/').next().unwrap_or(rel);
+    let name = decode_path_segment(name)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| name.to_owned());
+    conn.execute(
+        "INSERT INTO files (path, name, depth, is_dir, size, mtime, scan)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT (path) DO UPDATE SET is_dir = ?4, size = ?5, mtime = ?6, scan = ?7",
+        params![
+            rel,
+            name,
+            rel.split('/').count() as i64,
+            metadata.is_dir(),
+            size,
+            mtime,
+            scan
+        ],
+    )?;
+    let id: i64 = conn.query_row(
+        "SELECT id FROM files WHERE path = ?1",
+        params![rel],
+        |row| row.get(0),
+    )?;
+    let text = if content && metadata.is_file() {
+        read_text(path, metadata)
+    } else {
+        None
+    };
+    conn.execute(
+        "INSERT INTO files_fts (rowid, name, content) VALUES (?1, ?2, ?3)",
+        params![id, name, text.unwrap_or_default()],
+    )?;
+    Ok(())
+}
+
+/// Text of a small text file, `None` for anything that looks binary
+fn read_text(path: &Path, metadata: &fs::Metadata) -> Option<String> {
+    if metadata.len() > MAX_CONTENT_SIZE {
+        return None;
+    }
+    let mime = mime_types::from_path(path).first_or_octet_stream();
+    let mut buf = Vec::new();
+    File::open(path).ok()?.read_to_end(&mut buf).ok()?;
+    if mime.type_() != "text" && buf[..buf.len().min(8192)].contains(&0) {
+        return None;
+    }
+    String::from_utf8(buf).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::SymlinkPolicy;
+
+    #[test]
+    fn database_inside_root_is_filtered() {
+        let root = std::env::temp_dir().join(format!("search-index-test-{}", std::process::id()));
+        fs::create_dir_all(root.join("data")).unwrap();
+        fs::write(root.join("data/notes.txt"), "notes").unwrap();
+        let root = root.canonicalize().unwrap();
+        let filter = Arc::new(EntryFilter::new(
+            root.clone(),
+            SymlinkPolicy::WithinRoot,
+            true,
+            Vec::new(),
+            false,
+        ));
+
+        let db_path = root.join("data/index.db");
+        let index = SearchIndex::open(&db_path, &root, false, Arc::clone(&filter)).unwrap();
+        assert!(db_path.is_file());
+        for suffix in DB_FILE_SUFFIXES {
+            let name = format!("index.db{}", suffix);
+            let path = root.join("data").join(&name);
+            assert!(filter.is_filtered(&path, false), "{} is not filtered", name);
+            assert!(filter.is_filtered_entry(&path, false), "{} is listed", name);
+        }
+        assert!(!filter.is_filtered(&root.join("data/notes.txt"), false));
+        assert!(!filter.is_filtered(&root.join("data/index.db.txt"), false));
+
+        drop(index);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// How entry names are compared when sorting by name (`--name-collation`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Collation {
+    /// Case-insensitive, digit runs compared by value: `file2` < `file10`
+    Natural,
+    /// Case-insensitive
+    CaseInsensitive,
+    /// Byte-wise, uppercase before lowercase
+    Bytewise,
+}
+
+impl Collation {
+    pub const VALUES: [&'static str; 3] = ["natural", "case-insensitive", "bytewise"];
+
+    pub fn from_arg(value: &str) -> Option<Collation> {
+        match value {
+            "natural" => Some(Collation::Natural),
+            "case-insensitive" => Some(Collation::CaseInsensitive),
+            "bytewise" => Some(Collation::Bytewise),
+            _ => None,
+        }
+    }
+
+    /// Names equal but for their case (or leading zeros) fall back to the byte order,
+    /// so the result is deterministic
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Natural => natural_cmp(a, b).then_with(|| a.cmp(b)),
+            Collation::CaseInsensitive => caseless_chars(a)
+                .cmp(caseless_chars(b))
+                .then_with(|| a.cmp(b)),
+            Collation::Bytewise => a.cmp(b),
+        }
+    }
+}
+
+fn caseless_chars(s: &str) -> impl Iterator<Item = char> + '_ {
+    s.chars().flat_map(char::to_lowercase)
+}
+
+/// Case-insensitive comparison where runs of ASCII digits compare as numbers
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let ordering = compare_numbers(&digit_run(&mut a), &digit_run(&mut b));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                a.next();
+                b.next();
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+        }
+    }
+}
+
+fn digit_run(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
+}
+
+/// Compare digit strings by value, without overflow on long runs
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted<'a>(collation: Collation, names: &[&'a str]) -> Vec<&'a str> {
+        let mut names = names.to_vec();
+        names.sort_by(|a, b| collation.compare(a, b));
+        names
+    }
+
+    #[test]
+    fn natural_numbers_by_value() {
+        assert_eq!(
+            sorted(Collation::Natural, &["file10", "file2", "File1"]),
+            ["File1", "file2", "file10"]
+        );
+    }
+
+    #[test]
+    fn natural_leading_zeros() {
+        assert_eq!(natural_cmp("a01", "a1"), Ordering::Equal);
+        assert_eq!(natural_cmp("a01", "a2"), Ordering::Less);
+        assert_eq!(compare_numbers("007", "7"), Ordering::Equal);
+        // Still a total order
+        assert_eq!(Collation::Natural.compare("a01", "a1"), Ordering::Less);
+        assert_eq!(Collation::Natural.compare("a1", "a01"), Ordering::Greater);
+    }
+
+    #[test]
+    fn natural_digit_runs_longer_than_u64() {
+        // u64::MAX + 2 and u64::MAX + 1
+        assert_eq!(
+            natural_cmp("x18446744073709551617", "x18446744073709551616"),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare_numbers("123456789012345678901234567890", "99"),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare_numbers(&"9".repeat(40), &format!("1{}", "0".repeat(40))),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn case_insensitive_mixed_case() {
+        let names = ["banana", "Cherry", "apple", "Banana"];
+        assert_eq!(
+            sorted(Collation::CaseInsensitive, &names),
+            ["apple", "Banana", "banana", "Cherry"]
+        );
+        assert_eq!(
+            Collation::CaseInsensitive.compare("README", "readme"),
+            Ordering::Less
+        );
+        assert_eq!(
+            Collation::CaseInsensitive.compare("Zebra", "apple"),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn bytewise_non_ascii() {
+        assert_eq!(
+            sorted(Collation::Bytewise, &["\u{e9}t\u{e9}", "zoo", "Zoo", "ete"]),
+            ["Zoo", "ete", "zoo", "\u{e9}t\u{e9}"]
+        );
+        assert_eq!(
+            Collation::Bytewise.compare("\u{c5}", "\u{e5}"),
+            Ordering::Less
+        );
+    }
+}
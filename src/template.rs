@@ -0,0 +1,161 @@
+use std::path::{Path, PathBuf};
+
+use handlebars::Handlebars;
+use serde::Serialize;
+
+use crate::columns::ColumnValues;
+use crate::listing::Pagination;
+
+/// URL path (below base_url) of the template's `static` directory
+pub const STATIC_PATH: &str = "_template";
+
+const LISTING_TEMPLATE: &str = "listing";
+const ERROR_TEMPLATE: &str = "error";
+
+/// Handlebars templates from `--template DIR`
+///
+/// * `DIR/listing.hbs`: directory listing (required)
+/// * `DIR/error.hbs`: error page (optional)
+/// * `DIR/static/`: assets served under `{base_url}_template/`
+///
+/// Templates are reloaded when they change.
+pub struct Templates {
+    registry: Handlebars<'static>,
+    static_dir: PathBuf,
+}
+
+impl Templates {
+    pub fn load(dir: &Path) -> Result<Templates, String> {
+        let mut registry = Handlebars::new();
+        registry.set_dev_mode(true);
+        registry
+            .register_template_file(LISTING_TEMPLATE, dir.join("listing.hbs"))
+            .map_err(|e| format!("Template {}: {}", dir.join("listing.hbs").display(), e))?;
+        let error_path = dir.join("error.hbs");
+        if error_path.is_file() {
+            registry
+                .register_template_file(ERROR_TEMPLATE, &error_path)
+                .map_err(|e| format!("Template {}: {}", error_path.display(), e))?;
+        }
+        Ok(Templates {
+            registry,
+            static_dir: dir.join("static"),
+        })
+    }
+
+    pub fn static_dir(&self) -> &Path {
+        &self.static_dir
+    }
+
+    pub fn render_listing(&self, context: &ListingContext) -> Result<String, String> {
+        self.registry
+            .render(LISTING_TEMPLATE, context)
+            .map_err(|e| format!("Template error: {}", e))
+    }
+
+    /// `None` without an error template
+    pub fn render_error(&self, context: &ErrorContext) -> Option<Result<String, String>> {
+        if !self.registry.has_template(ERROR_TEMPLATE) {
+            return None;
+        }
+        Some(
+            self.registry
+                .render(ERROR_TEMPLATE, context)
+                .map_err(|e| format!("Template error: {}", e)),
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct Link {
+    pub name: String,
+    pub href: String,
+}
+
+#[derive(Serialize)]
+pub struct TemplateEntry {
+    pub name: String,
+    pub href: String,
+    pub is_dir: bool,
+    pub size: u64,
+    /// Human readable size, `-` for directories
+    pub size_label: String,
+    /// `%Y-%m-%d %H:%M:%S`
+    pub modified: String,
+    pub mime: Option<String>,
+    pub symlink_target: Option<String>,
+    /// `?preview` link of Markdown, source code and CSV files
+    pub preview: Option<String>,
+    /// `?thumb=` link of images
+    pub thumbnail: Option<String>,
+    /// `?play` link of audio and video files
+    pub play: Option<String>,
+    /// `mode`, `owner`, `group`, `items` and `dir_size` of the enabled columns
+    #[serde(flatten)]
+    pub columns: ColumnValues,
+}
+
+#[derive(Serialize)]
+pub struct SortLinks {
+    pub field: Option<String>,
+    pub order: Option<String>,
+    pub dirs_first: bool,
+    pub name: String,
+    pub modified: String,
+    pub size: String,
+    pub ext: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Only with `--columns dirsize`
+    pub dirsize: Option<String>,
+    /// Same order with `dirs_first` toggled
+    pub toggle_dirs_first: String,
+}
+
+#[derive(Serialize)]
+pub struct UploadForm {
+    pub action: String,
+    pub csrf_token: String,
+}
+
+#[derive(Serialize)]
+pub struct ListingContext {
+    pub base_url: String,
+    /// Base URL of the template's static assets
+    pub static_url: String,
+    /// Current directory, `/` separated, empty for the root
+    pub path: String,
+    /// Root first, the current directory last
+    pub breadcrumbs: Vec<Link>,
+    pub parent: Option<String>,
+    pub entries: Vec<TemplateEntry>,
+    /// Names of the `--columns` enabled
+    pub columns: Vec<&'static str>,
+    pub sort_links: Option<SortLinks>,
+    pub upload: Option<UploadForm>,
+    pub csrf_token: Option<String>,
+    /// Whole directory archive downloads (`zip`, `tar.gz`)
+    pub downloads: Vec<Link>,
+    pub search: Option<String>,
+    pub search_truncated: bool,
+    /// Only the entries of the current page are given
+    pub pagination: Option<Pagination>,
+    /// Sanitized HTML of the header and README files (`--readme`)
+    pub header: Option<String>,
+    pub readme: Option<String>,
+    /// `?view=gallery`, only directories and images are given
+    pub gallery: bool,
+    /// `?play` link of the directory, when it has audio or video files
+    pub play_all: Option<String>,
+    pub dropbox: bool,
+    pub archive: bool,
+}
+
+#[derive(Serialize)]
+pub struct ErrorContext {
+    pub base_url: String,
+    pub static_url: String,
+    pub code: u16,
+    pub reason: String,
+    pub message: String,
+}
@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use iron::status::{self, Status};
+use path_dedot::ParseDot;
+
+use crate::filter::EntryFilter;
+
+/// Placeholder of the requested path in `--try-files` candidates
+const URI: &str = "$uri";
+
+/// Link of the `--try-files` chain, tried in order for missing paths
+///
+/// Like nginx's `try_files`: `$uri.html`, `$uri/index.html`, `/index.html=200`,
+/// and a final `=404` to stop with a status.
+#[derive(Debug, Clone)]
+pub enum TryFile {
+    /// Root-relative path, `$uri` replaced by the requested path, and the status
+    /// it is sent with
+    File {
+        pattern: String,
+        status: Status,
+    },
+    Status(Status),
+}
+
+impl FromStr for TryFile {
+    type Err = String;
+
+    /// `PATTERN`, `PATTERN=STATUS` or `=STATUS` (an error status)
+    fn from_str(s: &str) -> Result<TryFile, String> {
+        let (pattern, code) = match s.rfind('=') {
+            Some(i) if s[i + 1..].len() == 3 && s[i + 1..].bytes().all(|b| b.is_ascii_digit()) => {
+                (&s[..i], Some(&s[i + 1..]))
+            }
+            _ => (s, None),
+        };
+        let status = match code {
+            Some(code) => {
+                let code = code.parse::<u16>().unwrap();
+                // Ending the chain with a success would send the error as a page
+                let min = if pattern.is_empty() { 400 } else { 200 };
+                if !(min..600).contains(&code) {
+                    return Err(format!(
+                        "Invalid status: {} ({} to 599 expected)",
+                        code, min
+                    ));
+                }
+                Status::from_u16(code)
+            }
+            None => status::Ok,
+        };
+        if pattern.is_empty() {
+            return match code {
+                Some(_) => Ok(TryFile::Status(status)),
+                None => Err("Empty candidate".to_owned()),
+            };
+        }
+        Ok(TryFile::File {
+            pattern: pattern.to_owned(),
+            status,
+        })
+    }
+}
+
+impl TryFile {
+    /// The file of this candidate for the request of `uri` (`/about`), if it exists
+    /// in `root` and is not filtered
+    pub fn resolve(&self, root: &Path, uri: &str, filter: &EntryFilter) -> Option<PathBuf> {
+        let pattern = match self {
+            TryFile::File { pattern, .. } => pattern,
+            TryFile::Status(_) => return None,
+        };
+        let candidate = pattern.replace(URI, uri);
+        let path = root
+            .join(candidate.trim_start_matches('/'))
+            .parse_dot()
+            .ok()?
+            .into_owned();
+        if path.starts_with(root) && path.is_file() && !filter.is_filtered(&path, false) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::filter::SymlinkPolicy;
+
+    fn pattern(try_file: &TryFile) -> (&str, u16) {
+        match try_file {
+            TryFile::File { pattern, status } => (pattern, status.to_u16()),
+            TryFile::Status(status) => panic!("status {} instead of a file", status),
+        }
+    }
+
+    #[test]
+    fn parse_file() {
+        let try_file = "$uri.html".parse::<TryFile>().unwrap();
+        assert_eq!(pattern(&try_file), ("$uri.html", 200));
+        let try_file = "/404.html=404".parse::<TryFile>().unwrap();
+        assert_eq!(pattern(&try_file), ("/404.html", 404));
+    }
+
+    #[test]
+    fn parse_file_with_equal_sign() {
+        let try_file = "/page=".parse::<TryFile>().unwrap();
+        assert_eq!(pattern(&try_file), ("/page=", 200));
+        let try_file = "/a=b.html".parse::<TryFile>().unwrap();
+        assert_eq!(pattern(&try_file), ("/a=b.html", 200));
+    }
+
+    #[test]
+    fn parse_status() {
+        match "=410".parse::<TryFile>().unwrap() {
+            TryFile::Status(status) => assert_eq!(status.to_u16(), 410),
+            TryFile::File { .. } => panic!("file instead of a status"),
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!("".parse::<TryFile>().is_err());
+        assert!("=200".parse::<TryFile>().is_err());
+        assert!("=302".parse::<TryFile>().is_err());
+        assert!("=600".parse::<TryFile>().is_err());
+        assert!("/index.html=199".parse::<TryFile>().is_err());
+        assert!("/index.html=200".parse::<TryFile>().is_ok());
+    }
+
+    #[test]
+    fn resolve_stays_in_root() {
+        let dir = std::env::temp_dir().join(format!("try-files-test-{}", std::process::id()));
+        let root = dir.join("root");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(dir.join("secret.html"), "secret").unwrap();
+        fs::write(root.join("about.html"), "about").unwrap();
+        let root = root.canonicalize().unwrap();
+        let filter = EntryFilter::new(
+            root.clone(),
+            SymlinkPolicy::WithinRoot,
+            false,
+            Vec::new(),
+            false,
+        );
+
+        let try_file = "$uri.html".parse::<TryFile>().unwrap();
+        assert_eq!(
+            try_file.resolve(&root, "/about", &filter),
+            Some(root.join("about.html"))
+        );
+        assert_eq!(try_file.resolve(&root, "/missing", &filter), None);
+        assert_eq!(try_file.resolve(&root, "/../secret", &filter), None);
+        assert_eq!(try_file.resolve(&root, "/x/../../secret", &filter), None);
+        let try_file = "../secret.html".parse::<TryFile>().unwrap();
+        assert_eq!(try_file.resolve(&root, "/", &filter), None);
+        let try_file = "=410".parse::<TryFile>().unwrap();
+        assert_eq!(try_file.resolve(&root, "/about", &filter), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,17 +1,26 @@
+use std::borrow::Cow;
 use std::error::Error;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
 use std::io;
 use std::ops::Deref;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::fs::File;
 
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
 use chrono::{DateTime, Local, TimeZone};
 use iron::headers;
 use iron::status;
 use iron::{IronError, Response};
-use percent_encoding::{utf8_percent_encode, AsciiSet};
+use percent_encoding::{
+    percent_decode, percent_encode, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC,
+};
+use sha2::{Digest, Sha256};
 use wasmtime::{Engine, Module};
 
+use crate::template::{self, ErrorContext, Templates};
+
 pub enum Status {
     BadRequest,
 }
@@ -61,13 +70,180 @@ pub fn enable_string(value: bool) -> String {
     (if value { "enabled" } else { "disabled" }).to_owned()
 }
 
-pub fn encode_link_path(path: &[String]) -> String {
+/// Percent-encode path segments, names that are not valid UTF-8 keep their raw bytes
+pub fn encode_link_path<S: AsRef<OsStr>>(path: &[S]) -> String {
     path.iter()
-        .map(|s| utf8_percent_encode(s, PATH_SEGMENT_ENCODE_SET).to_string())
+        .map(|s| percent_encode(&os_str_bytes(s.as_ref()), PATH_SEGMENT_ENCODE_SET).to_string())
         .collect::<Vec<String>>()
         .join("/")
 }
 
+/// Decode a percent-encoded URL path segment back to a file name
+///
+/// Any byte sequence is accepted on Unix, other systems require UTF-8.
+pub fn decode_path_segment(segment: &str) -> Option<OsString> {
+    let bytes: Vec<u8> = percent_decode(segment.as_bytes()).collect();
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        Some(OsString::from_vec(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        String::from_utf8(bytes).ok().map(OsString::from)
+    }
+}
+
+/// Path segments joined with `/` for display, lossy for names that are not valid UTF-8
+pub fn display_path(path: &[OsString]) -> String {
+    path.iter()
+        .map(|s| s.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Raw bytes of a file name on Unix, its UTF-8 (lossy) encoding elsewhere
+pub fn os_str_bytes(s: &OsStr) -> Cow<'_, [u8]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(s.as_bytes())
+    }
+    #[cfg(not(unix))]
+    {
+        match s.to_string_lossy() {
+            Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+            Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+        }
+    }
+}
+
+/// Writer adapter that computes the SHA-256 digest of everything written through it
+pub struct Sha256Writer<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: io::Write> Sha256Writer<W> {
+    pub fn new(inner: W) -> Sha256Writer<W> {
+        Sha256Writer {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        self.hasher.finalize().to_vec()
+    }
+}
+
+impl<W: io::Write> io::Write for Sha256Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Insert a counter before the extension: `report.pdf` => `report (1).pdf`
+pub fn numbered_filename(filename: &str, n: usize) -> String {
+    match filename.rfind('.') {
+        Some(idx) if idx > 0 => format!("{} ({}){}", &filename[..idx], n, &filename[idx..]),
+        _ => format!("{} ({})", filename, n),
+    }
+}
+
+pub fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse a hex or base64 encoded SHA-256 digest (as sent in the `sha256` form field)
+pub fn parse_sha256(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim();
+    let bytes = if value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        (0..64)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16).unwrap())
+            .collect()
+    } else {
+        STANDARD.decode(value).ok()?
+    };
+    if bytes.len() == 32 {
+        Some(bytes)
+    } else {
+        None
+    }
+}
+
+/// Extract the `sha-256` member from a `Content-Digest`/`Repr-Digest` header value (RFC 9530)
+///
+/// Example: `sha-256=:X48E9qOokqqrvdts8nOJRJN3OWDUoyWxBf7kbu9DBPE=:, sha-512=:...:`
+pub fn parse_digest_header(value: &str) -> Result<Option<Vec<u8>>, String> {
+    for member in value.split(',') {
+        let mut parts = member.trim().splitn(2, '=');
+        let algorithm = parts.next().unwrap_or("").trim();
+        if !algorithm.eq_ignore_ascii_case("sha-256") {
+            continue;
+        }
+        let encoded = parts.next().unwrap_or("").trim();
+        if encoded.len() < 2 || !encoded.starts_with(':') || !encoded.ends_with(':') {
+            return Err(format!("invalid sha-256 digest: {}", encoded));
+        }
+        return match STANDARD.decode(&encoded[1..encoded.len() - 1]) {
+            Ok(bytes) if bytes.len() == 32 => Ok(Some(bytes)),
+            _ => Err(format!("invalid sha-256 digest: {}", encoded)),
+        };
+    }
+    Ok(None)
+}
+
+/// Expected digest of each of `files` uploaded files: its own `sha256` field
+/// (matched by position), or else the `Content-Digest` of the request, which
+/// only describes a single file
+pub fn expected_digests(
+    part_digests: Vec<Option<Vec<u8>>>,
+    request_digest: Option<Vec<u8>>,
+    files: usize,
+) -> Result<Vec<Option<Vec<u8>>>, String> {
+    if request_digest.is_some() && files > 1 {
+        return Err(String::from(
+            "Content-Digest requires a single uploaded file",
+        ));
+    }
+    let mut part_digests = part_digests.into_iter();
+    Ok((0..files)
+        .map(|_| {
+            part_digests
+                .next()
+                .unwrap_or(None)
+                .or_else(|| request_digest.clone())
+        })
+        .collect())
+}
+
+/// `Content-Disposition` header value for downloading `filename` as an attachment
+pub fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(filename, NON_ALPHANUMERIC)
+    )
+}
+
 pub fn error_io2iron(err: io::Error) -> IronError {
     let status = match err.kind() {
         io::ErrorKind::PermissionDenied => status::Forbidden,
@@ -147,7 +323,27 @@ pub fn system_time_to_date_time(t: SystemTime) -> DateTime<Local> {
     Local.timestamp_opt(sec, nsec).unwrap()
 }
 
-pub fn error_resp(s: status::Status, msg: &str, baseurl: &str) -> Response {
+pub fn error_resp(
+    s: status::Status,
+    msg: &str,
+    baseurl: &str,
+    templates: Option<&Templates>,
+) -> Response {
+    if let Some(templates) = templates {
+        let context = ErrorContext {
+            base_url: baseurl.to_owned(),
+            static_url: format!("{}{}/", baseurl, template::STATIC_PATH),
+            code: s.to_u16(),
+            reason: s.canonical_reason().unwrap_or("").to_owned(),
+            message: msg.to_owned(),
+        };
+        // A broken template falls back to the built-in page
+        if let Some(Ok(html)) = templates.render_error(&context) {
+            let mut resp = Response::with((s, html));
+            resp.headers.set(headers::ContentType::html());
+            return resp;
+        }
+    }
     let mut resp = Response::with((
         s,
         format!(
@@ -192,4 +388,88 @@ pub fn get_permission(path: &str) {
     // CWE-732
     //SINK
     let _ = chmod(Path::new(path), mode);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO_HEX: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const HELLO_BASE64: &str = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+
+    fn hello() -> Vec<u8> {
+        Sha256::digest(b"hello").to_vec()
+    }
+
+    #[test]
+    fn parse_sha256_hex() {
+        assert_eq!(parse_sha256(HELLO_HEX), Some(hello()));
+        assert_eq!(parse_sha256(&HELLO_HEX.to_uppercase()), Some(hello()));
+        assert_eq!(parse_sha256(&format!(" {}\n", HELLO_HEX)), Some(hello()));
+    }
+
+    #[test]
+    fn parse_sha256_base64() {
+        assert_eq!(parse_sha256(HELLO_BASE64), Some(hello()));
+    }
+
+    #[test]
+    fn parse_sha256_invalid() {
+        assert_eq!(parse_sha256(""), None);
+        assert_eq!(parse_sha256(&HELLO_HEX[..62]), None);
+        assert_eq!(parse_sha256(&HELLO_HEX.replace('c', "g")), None);
+        // Valid base64 of the wrong length
+        assert_eq!(parse_sha256("aGVsbG8="), None);
+    }
+
+    #[test]
+    fn parse_digest_header_sha256() {
+        let header = format!("sha-256=:{}:", HELLO_BASE64);
+        assert_eq!(parse_digest_header(&header), Ok(Some(hello())));
+        let header = format!("SHA-256 = :{}:", HELLO_BASE64);
+        assert_eq!(parse_digest_header(&header), Ok(Some(hello())));
+    }
+
+    #[test]
+    fn parse_digest_header_unknown_algorithms() {
+        assert_eq!(parse_digest_header("sha-512=:AAAA:, md5=:AAAA:"), Ok(None));
+        assert_eq!(parse_digest_header(""), Ok(None));
+    }
+
+    #[test]
+    fn parse_digest_header_several_digests() {
+        let header = format!("sha-512=:AAAA:, sha-256=:{}:, unixsum=:1:", HELLO_BASE64);
+        assert_eq!(parse_digest_header(&header), Ok(Some(hello())));
+    }
+
+    #[test]
+    fn parse_digest_header_invalid() {
+        assert!(parse_digest_header(&format!("sha-256={}", HELLO_BASE64)).is_err());
+        assert!(parse_digest_header("sha-256=:aGVsbG8=:").is_err());
+        assert!(parse_digest_header("sha-256=::").is_err());
+        assert!(parse_digest_header("sha-256").is_err());
+    }
+
+    #[test]
+    fn expected_digests_by_position() {
+        let other = vec![0; 32];
+        let expected = expected_digests(vec![None, Some(other.clone())], None, 3);
+        assert_eq!(expected, Ok(vec![None, Some(other), None]));
+    }
+
+    #[test]
+    fn expected_digests_content_digest_single_file() {
+        assert_eq!(
+            expected_digests(Vec::new(), Some(hello()), 1),
+            Ok(vec![Some(hello())])
+        );
+        // The file's own field wins
+        let other = vec![0; 32];
+        assert_eq!(
+            expected_digests(vec![Some(other.clone())], Some(hello()), 1),
+            Ok(vec![Some(other)])
+        );
+        assert!(expected_digests(Vec::new(), Some(hello()), 2).is_err());
+        assert_eq!(expected_digests(Vec::new(), None, 2), Ok(vec![None, None]));
+    }
+}